@@ -1,7 +1,20 @@
 use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
 
-use dagrid_core::control::ControlGraph;
+use dagrid_core::control::{ControlGraph, JitProgram, Program};
+use dagrid_core::pool::ThreadPool;
 use dagrid_core::presets::{self, preset};
+use dagrid_core::Sample;
+
+/// Renders 48000 samples in blocks of up to `block.len()`, so that every block length renders the
+/// same amount of audio.
+fn one_second(block: &mut [Sample], mut process: impl FnMut(&mut [Sample])) {
+    for start in (0..48000).step_by(block.len()) {
+        let len = block.len().min(48000 - start);
+        let block = &mut block[..len];
+        process(block);
+        black_box(&block);
+    }
+}
 
 fn construct(c: &mut Criterion) {
     let mut g = c.benchmark_group("construct");
@@ -51,10 +64,143 @@ fn synth(c: &mut Criterion) {
     g.finish();
 }
 
+fn synth_block(c: &mut Criterion) {
+    let mut g = c.benchmark_group("synth_block");
+
+    fn synth_block_x(cg: &mut ControlGraph, block: &mut [Sample]) {
+        one_second(block, |block| cg.process_block(block));
+    }
+
+    for block_len in [64, 512] {
+        g.bench_function(format!("subsynth_plain/{block_len}"), |b| {
+            let mut block = vec![Sample::default(); block_len];
+            b.iter_batched(
+                || preset(48000, presets::subsynth_plain),
+                |mut cg| synth_block_x(&mut cg, &mut block),
+                BatchSize::SmallInput,
+            )
+        });
+
+        g.bench_function(format!("subsynth_with_containers/{block_len}"), |b| {
+            let mut block = vec![Sample::default(); block_len];
+            b.iter_batched(
+                || preset(48000, presets::subsynth_with_containers),
+                |mut cg| synth_block_x(&mut cg, &mut block),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    g.finish();
+}
+
+fn synth_parallel(c: &mut Criterion) {
+    let mut g = c.benchmark_group("synth_parallel");
+    let pool = ThreadPool::default();
+
+    fn synth_parallel_x(cg: &mut ControlGraph, block: &mut [Sample], pool: &ThreadPool) {
+        one_second(block, |block| cg.process_block_parallel(block, pool));
+    }
+
+    for block_len in [512, 4096] {
+        g.bench_function(format!("subsynth_plain/{block_len}"), |b| {
+            let mut block = vec![Sample::default(); block_len];
+            b.iter_batched(
+                || preset(48000, presets::subsynth_plain),
+                |mut cg| synth_parallel_x(&mut cg, &mut block, &pool),
+                BatchSize::SmallInput,
+            )
+        });
+
+        g.bench_function(format!("subsynth_with_containers/{block_len}"), |b| {
+            let mut block = vec![Sample::default(); block_len];
+            b.iter_batched(
+                || preset(48000, presets::subsynth_with_containers),
+                |mut cg| synth_parallel_x(&mut cg, &mut block, &pool),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    g.finish();
+}
+
+fn synth_compiled(c: &mut Criterion) {
+    let mut g = c.benchmark_group("synth_compiled");
+
+    fn synth_compiled_x(program: &mut Program) {
+        for _ in 0..48000 {
+            black_box(program.next_sample());
+        }
+    }
+
+    g.bench_function("subsynth_plain", |b| {
+        b.iter_batched(
+            || preset(48000, presets::subsynth_plain).compile().unwrap(),
+            |mut program| synth_compiled_x(&mut program),
+            BatchSize::SmallInput,
+        )
+    });
+
+    g.bench_function("subsynth_with_containers", |b| {
+        b.iter_batched(
+            || {
+                preset(48000, presets::subsynth_with_containers)
+                    .compile()
+                    .unwrap()
+            },
+            |mut program| synth_compiled_x(&mut program),
+            BatchSize::SmallInput,
+        )
+    });
+
+    g.finish();
+}
+
+fn synth_jit(c: &mut Criterion) {
+    let mut g = c.benchmark_group("synth_jit");
+
+    fn synth_jit_x(jit: &mut JitProgram, block: &mut [Sample]) {
+        one_second(block, |block| jit.process(block));
+    }
+
+    for block_len in [1, 64, 512] {
+        g.bench_function(format!("subsynth_plain/{block_len}"), |b| {
+            let mut block = vec![Sample::default(); block_len];
+            b.iter_batched(
+                || {
+                    preset(48000, presets::subsynth_plain)
+                        .compile()
+                        .unwrap()
+                        .jit()
+                },
+                |mut jit| synth_jit_x(&mut jit, &mut block),
+                BatchSize::SmallInput,
+            )
+        });
+
+        g.bench_function(format!("subsynth_with_containers/{block_len}"), |b| {
+            let mut block = vec![Sample::default(); block_len];
+            b.iter_batched(
+                || {
+                    preset(48000, presets::subsynth_with_containers)
+                        .compile()
+                        .unwrap()
+                        .jit()
+                },
+                |mut jit| synth_jit_x(&mut jit, &mut block),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    g.finish();
+}
+
 criterion_group! {
     name = benches;
     config = Criterion::default();
-    targets = synth, construct
+    targets = synth, synth_block, synth_parallel, synth_compiled, synth_jit, construct
 }
 
 criterion_main!(benches);
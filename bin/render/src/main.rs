@@ -0,0 +1,138 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, ValueEnum};
+use dagrid_core::control::ControlGraph;
+use dagrid_core::presets;
+use dagrid_core::Sample;
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+const BLOCK_LEN: usize = 512;
+
+/// Renders a control graph to a stereo WAV file.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Graph to render, as written by `ControlGraph::save`
+    #[arg(required_unless_present = "preset", conflicts_with = "preset")]
+    graph: Option<PathBuf>,
+
+    /// Renders the preset with this name instead of a saved graph
+    #[arg(short, long)]
+    preset: Option<String>,
+
+    /// Where to write the WAV file
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Length of the render, in seconds
+    #[arg(short, long, default_value_t = 5.0)]
+    seconds: f64,
+
+    #[arg(short = 'r', long, default_value_t = 44100)]
+    sample_rate: u32,
+
+    #[arg(short, long, value_enum, default_value_t = Format::Int16)]
+    format: Format,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    /// 16-bit integer
+    #[value(name = "16")]
+    Int16,
+    /// 24-bit integer
+    #[value(name = "24")]
+    Int24,
+    /// 32-bit float
+    #[value(name = "32f")]
+    Float32,
+}
+
+impl Format {
+    fn spec(self, sample_rate: u32) -> WavSpec {
+        let (bits_per_sample, sample_format) = match self {
+            Format::Int16 => (16, SampleFormat::Int),
+            Format::Int24 => (24, SampleFormat::Int),
+            Format::Float32 => (32, SampleFormat::Float),
+        };
+
+        WavSpec {
+            channels: 2,
+            sample_rate,
+            bits_per_sample,
+            sample_format,
+        }
+    }
+}
+
+fn load_graph(args: &Args) -> Result<ControlGraph, Box<dyn Error>> {
+    if let Some(name) = &args.preset {
+        let f = presets::named(name).ok_or_else(|| {
+            let names = presets::PRESETS.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+            format!(
+                "there's no preset named \"{name}\" (expected one of: {})",
+                names.join(", ")
+            )
+        })?;
+
+        return Ok(presets::preset(args.sample_rate, f));
+    }
+
+    // `required_unless_present` guarantees a graph when there's no preset
+    let path = args.graph.as_ref().unwrap();
+    let data = std::fs::read(path).map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+
+    Ok(ControlGraph::load(args.sample_rate, &data)?)
+}
+
+fn main() -> ExitCode {
+    match render(&Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn render(args: &Args) -> Result<(), Box<dyn Error>> {
+    if !(args.seconds.is_finite() && args.seconds >= 0.0) {
+        return Err(format!("can't render {} seconds", args.seconds).into());
+    }
+
+    let mut cg = load_graph(args)?;
+    let mut writer = WavWriter::create(&args.output, args.format.spec(args.sample_rate))?;
+
+    let mut remaining = (args.seconds * args.sample_rate as f64).round() as usize;
+    let mut block = [Sample::default(); BLOCK_LEN];
+
+    while remaining > 0 {
+        let block = &mut block[..remaining.min(BLOCK_LEN)];
+        cg.try_process_block(block)?;
+
+        for s in block.iter() {
+            for val in [s.l(), s.r()] {
+                match args.format {
+                    Format::Int16 => writer.write_sample(quantize(val, 16) as i16)?,
+                    Format::Int24 => writer.write_sample(quantize(val, 24))?,
+                    Format::Float32 => writer.write_sample(val as f32)?,
+                }
+            }
+        }
+
+        remaining -= block.len();
+    }
+
+    writer.finalize()?;
+
+    Ok(())
+}
+
+/// Scales `val` to a signed integer with `bits` bits, clipping anything outside of -1 to 1.
+fn quantize(val: f64, bits: u32) -> i32 {
+    let max = ((1 << (bits - 1)) - 1) as f64;
+
+    (val.clamp(-1.0, 1.0) * max).round() as i32
+}
@@ -1,7 +1,11 @@
+use std::ops::Deref;
+
 use petgraph::graph::NodeIndex;
 
 use crate::control::ControlGraph;
+use crate::error::GraphError;
 use crate::node::*;
+use crate::Sample;
 
 pub trait Container {
     fn get_ident(&self) -> &str;
@@ -10,6 +14,49 @@ pub trait Container {
     fn construct(&self, inputs: &[NodeIndex], outputs: &[NodeIndex], cg: &mut ControlGraph);
 }
 
+/// The input or output ports of an inserted container, in the order of its labels.
+/// Returned by [ControlGraph::insert_container].
+#[derive(Debug, Clone)]
+pub struct ContainerPorts {
+    nodes: Vec<NodeIndex>,
+    labels: Vec<String>,
+}
+
+impl ContainerPorts {
+    pub(crate) fn new(nodes: Vec<NodeIndex>, labels: &[&str]) -> Self {
+        Self {
+            nodes,
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+        }
+    }
+
+    /// Returns the port labeled `label`.
+    /// Panics if there is no such port. See [ContainerPorts::try_named].
+    pub fn named(&self, label: &str) -> NodeIndex {
+        self.try_named(label).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Returns the port labeled `label`, or [GraphError::NoSuchContainerPort] if there is none.
+    pub fn try_named(&self, label: &str) -> Result<NodeIndex, GraphError> {
+        self.labels
+            .iter()
+            .position(|l| l == label)
+            .map(|i| self.nodes[i])
+            .ok_or_else(|| GraphError::NoSuchContainerPort {
+                label: label.into(),
+                valid: self.labels.clone(),
+            })
+    }
+}
+
+impl Deref for ContainerPorts {
+    type Target = [NodeIndex];
+
+    fn deref(&self) -> &Self::Target {
+        &self.nodes
+    }
+}
+
 pub struct Sub;
 impl Container for Sub {
     fn get_ident(&self) -> &str {
@@ -55,3 +102,92 @@ impl Container for Div {
         cg.connect_ex_ex(mul, outputs[0]);
     }
 }
+
+/// Builds a [FractionalDelay] whose time sweeps from `base` up to `base + range` seconds with a
+/// sine [Lfo], then mixes it with the dry input. The left and right channels are swept a quarter
+/// of a cycle apart, which widens the sound.
+///
+/// Takes the inputs `Input`, `Rate` (in Hz), `Depth` (0 to 1, scales the sweep) and `Mix`
+/// (0 is dry, 1 is wet).
+fn modulated_delay(
+    inputs: &[NodeIndex],
+    outputs: &[NodeIndex],
+    cg: &mut ControlGraph,
+    base: f64,
+    range: f64,
+) {
+    let lfo = cg.connect_ex_new(
+        inputs[1],
+        Lfo {
+            shape: LfoShape::Sine,
+            polarity: Polarity::Unipolar,
+        },
+    );
+    cg.connect_new_ex_port(Const(Sample::stereo(0.0, 0.25)), lfo, 1);
+    // the rate is in Hz rather than synced to the tempo
+    cg.connect_const_ex_port(0.0, lfo, 2);
+
+    let sweep = cg.connect_many_new(&[lfo, inputs[2]], Mul);
+    let sweep = cg.connect_ex_new(sweep, Mul);
+    cg.connect_const_ex_port(range, sweep, 1);
+    let time = cg.connect_const_new(base, Add);
+    cg.connect(sweep, time, 1);
+
+    let delay = cg.connect_many_new(
+        &[inputs[0], time],
+        FractionalDelay {
+            max_time: base + range,
+            interpolation: Interpolation::Cubic,
+        },
+    );
+
+    // dry + (wet - dry) * mix
+    let (sub_in, sub_out) = cg.insert_container(Sub);
+    cg.connect_ex_ex(delay, sub_in[0]);
+    cg.connect_ex_ex_port(inputs[0], sub_in[1], 0);
+    let wet = cg.connect_many_new(&[sub_out[0], inputs[3]], Mul);
+    let out = cg.connect_many_new(&[inputs[0], wet], Add);
+
+    cg.connect_ex_ex(out, outputs[0]);
+}
+
+/// A stereo chorus, which thickens its input with a copy delayed by 15 to 25 ms.
+pub struct Chorus;
+impl Container for Chorus {
+    fn get_ident(&self) -> &str {
+        "Chorus"
+    }
+
+    fn get_input_labels(&self) -> &[&str] {
+        &["Input", "Rate", "Depth", "Mix"]
+    }
+
+    fn get_output_labels(&self) -> &[&str] {
+        &["Output"]
+    }
+
+    fn construct(&self, inputs: &[NodeIndex], outputs: &[NodeIndex], cg: &mut ControlGraph) {
+        modulated_delay(inputs, outputs, cg, 0.015, 0.010);
+    }
+}
+
+/// A stereo flanger, which sweeps comb filtering across its input with a copy delayed by 1 to
+/// 5 ms.
+pub struct Flanger;
+impl Container for Flanger {
+    fn get_ident(&self) -> &str {
+        "Flanger"
+    }
+
+    fn get_input_labels(&self) -> &[&str] {
+        &["Input", "Rate", "Depth", "Mix"]
+    }
+
+    fn get_output_labels(&self) -> &[&str] {
+        &["Output"]
+    }
+
+    fn construct(&self, inputs: &[NodeIndex], outputs: &[NodeIndex], cg: &mut ControlGraph) {
+        modulated_delay(inputs, outputs, cg, 0.001, 0.004);
+    }
+}
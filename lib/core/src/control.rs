@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::ops::Range;
 
 use petgraph::algo::DfsSpace;
 use petgraph::csr::IndexType;
@@ -8,107 +9,286 @@ use petgraph::visit::{EdgeRef, Visitable};
 use petgraph::{Direction, Incoming, Outgoing};
 use serde::{Deserialize, Serialize};
 
-use crate::container::Container;
+use crate::container::{Container, ContainerPorts};
+use crate::error::{GraphError, LoadError};
 use crate::node::*;
+use crate::pool::ThreadPool;
 use crate::Sample;
 
+pub(crate) mod format;
+#[cfg(feature = "jit")]
+mod jit;
+mod program;
+
+/// The estimated work, in samples summed over a level's nodes, below which a level is processed
+/// on the calling thread rather than split over a [ThreadPool].
+const PARALLEL_MIN_WORK: usize = 2048;
+
+pub use format::FORMAT_VERSION;
+#[cfg(feature = "jit")]
+pub use jit::{JitProgram, NativeProgram};
+pub use program::{Instruction, Program};
+
 pub struct Neighbor {
     pub node_index: NodeIndex,
     pub edge_index: EdgeIndex,
+    pub src_port_id: usize,
     pub dest_port_id: usize,
 }
 
+/// The weight of an edge: which output port of the source node connects to which input port
+/// of the destination node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Edge {
+    pub src_port: usize,
+    pub dest_port: usize,
+}
+
+/// A connection from output `src_port` of `src` back through the [Feedback] node `tap`, which
+/// isn't an edge of the DAG so that it can form a loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeedbackEdge {
+    pub src: NodeIndex,
+    pub src_port: usize,
+    pub tap: NodeIndex,
+    pub delay: usize,
+}
+
+/// Determines what happens when an edge is connected to an input port that already has one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PortMerge {
+    /// Fails with [GraphError::PortOccupied].
+    #[default]
+    Reject,
+    /// Sums both edges by inserting an [Add] node in front of the port.
+    Sum,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NodeData {
     input_arena_ptr: usize,
+    output_arena_ptr: usize,
     #[serde(skip)]
     gen: u64,
+    /// Mutable per-instance state, sized by [Node::state_len].
     #[serde(skip)]
-    val: Sample,
+    state: Vec<Sample>,
     node: Box<dyn Node>,
 }
 
+impl NodeData {
+    fn new(
+        node: Box<dyn Node>,
+        input_arena_ptr: usize,
+        output_arena_ptr: usize,
+        gen: u64,
+        sample_rate: u32,
+    ) -> Self {
+        let mut data = Self {
+            input_arena_ptr,
+            output_arena_ptr,
+            gen,
+            state: vec![],
+            node,
+        };
+
+        data.init_state(sample_rate);
+
+        data
+    }
+
+    /// Allocates the node's state for `sample_rate` and resets it.
+    fn init_state(&mut self, sample_rate: u32) {
+        self.state
+            .resize(self.node.state_len(sample_rate), Sample::mono(0.0));
+        self.node.reset_state(&mut self.state);
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ControlGraph {
     #[serde(skip)]
     phase: u64,
     sample_rate: u32,
-    pub dag: StableDiGraph<NodeData, usize, u32>,
+    pub dag: StableDiGraph<NodeData, Edge, u32>,
     #[serde(skip)]
-    dag_cycle_state: DfsSpace<NodeIndex, <StableDiGraph<NodeData, usize, u32> as Visitable>::Map>,
-    node_input_arena: Vec<NodeIndex>,
+    dag_cycle_state: DfsSpace<NodeIndex, <StableDiGraph<NodeData, Edge, u32> as Visitable>::Map>,
+    /// The output slot that each input port reads from.
+    node_input_arena: Vec<usize>,
     node_input_val_arena: Vec<Sample>,
+    /// The value of every output port. Slot 0 belongs to `aout`, which is never evaluated, so
+    /// unconnected inputs read from it.
+    node_output_val_arena: Vec<Sample>,
     container_idents: Vec<String>,
     container_stack: Vec<usize>,
     container_members: Vec<Vec<NodeIndex>>,
     container_children: Vec<Vec<usize>>,
     aout_node: NodeIndex,
+    feedback: Vec<FeedbackEdge>,
+    macros: Vec<Macro>,
+    /// The output slot of each feedback source, along with its tap. Resolved when the cache is
+    /// rebuilt.
     #[serde(skip)]
-    cache: Vec<(NodeIndex, usize)>,
+    feedback_slots: Vec<(usize, NodeIndex)>,
+    /// Each node to evaluate, along with its (`input_arena_ptr`, `output_arena_ptr`).
+    #[serde(skip)]
+    cache: Vec<(NodeIndex, usize, usize)>,
     #[serde(skip)]
     cache_invalid: bool,
+    #[serde(skip)]
+    port_merge: PortMerge,
+    /// Whether the cache is optimized when it's rebuilt. See [ControlGraph::set_optimize].
+    #[serde(skip)]
+    optimize: bool,
+    /// Whether each output slot is written by a cached node, rather than holding a constant.
+    /// Built along with the cache.
+    #[serde(skip)]
+    dynamic_slots: Vec<bool>,
+    /// The output slot routed to `aout`, resolved when the cache is rebuilt.
+    #[serde(skip)]
+    aout_src: usize,
+    /// Mirrors `node_output_val_arena`, with each output slot holding `block_len` samples.
+    #[serde(skip)]
+    block_arena: Vec<Sample>,
+    /// Frame-interleaved outputs of a node with several outputs, before they're copied to
+    /// `block_arena`.
+    #[serde(skip)]
+    block_scratch: Vec<Sample>,
+    /// Mirrors `node_input_val_arena`, with each input slot holding `block_len` samples.
+    #[serde(skip)]
+    node_input_block_arena: Vec<Sample>,
+    #[serde(skip)]
+    block_len: usize,
+    /// Indices into `cache`, ordered by dependency level. Nodes in the same level don't read each
+    /// other's outputs, so they can be processed at the same time.
+    #[serde(skip)]
+    schedule: Vec<usize>,
+    /// The range of `schedule` that holds each level.
+    #[serde(skip)]
+    levels: Vec<Range<usize>>,
+    /// The nodes of the level that's being processed in parallel.
+    #[serde(skip)]
+    level_nodes: Vec<LevelNode>,
 }
 
+/// A node with a single output, in a level of the schedule that's being processed in parallel.
+#[derive(Debug, Clone, Copy)]
+struct LevelNode {
+    data: *mut NodeData,
+    input_arena_ptr: usize,
+    output_arena_ptr: usize,
+}
+
+// SAFETY: only dereferenced while its level is processed, by a single thread
+unsafe impl Send for LevelNode {}
+unsafe impl Sync for LevelNode {}
+
+/// The block arenas of a control graph, shared by the threads that process a level.
+#[derive(Clone, Copy)]
+struct BlockArenas {
+    outputs: *mut Sample,
+    inputs: *mut Sample,
+    len: usize,
+}
+
+// SAFETY: each node of a level only writes its own slots, and only reads output slots written
+// by earlier levels
+unsafe impl Send for BlockArenas {}
+unsafe impl Sync for BlockArenas {}
+
 impl ControlGraph {
     /// Returns a new control graph with its `sample_rate` set.
     pub fn new(sample_rate: u32) -> Self {
         let mut dag = StableDiGraph::new();
-        let aout_node = dag.add_node(NodeData {
-            input_arena_ptr: 0,
-            gen: 0,
-            val: Sample::default(),
-            node: Box::new(Empty),
-        });
+        let aout_node = dag.add_node(NodeData::new(Box::new(Empty), 0, 0, 0, sample_rate));
         Self {
             phase: 0,
             sample_rate,
             dag,
             dag_cycle_state: DfsSpace::default(),
-            node_input_arena: vec![0.into()],
+            node_input_arena: vec![0],
             node_input_val_arena: vec![f64::NAN.into()],
+            node_output_val_arena: vec![f64::NAN.into()],
             container_idents: vec![],
             container_stack: vec![],
             container_members: vec![],
             container_children: vec![vec![]],
             aout_node,
+            feedback: vec![],
+            macros: vec![Macro::default(); MACRO_COUNT],
+            feedback_slots: vec![],
             cache: vec![],
             cache_invalid: true,
+            port_merge: PortMerge::default(),
+            optimize: true,
+            dynamic_slots: vec![],
+            aout_src: 0,
+            block_arena: vec![],
+            block_scratch: vec![],
+            node_input_block_arena: vec![],
+            block_len: 0,
+            schedule: vec![],
+            levels: vec![],
+            level_nodes: vec![],
         }
     }
 
-    /// Loads a control graph from the postcard-encoded data.
-    pub fn load(sample_rate: u32, data: &[u8]) -> postcard::Result<Self> {
-        let mut cg: Self = postcard::from_bytes(data)?;
+    /// Loads a control graph from data written by [ControlGraph::save], migrating it if it was
+    /// saved in an older format version.
+    pub fn load(sample_rate: u32, data: &[u8]) -> Result<Self, LoadError> {
+        let mut cg: Self = postcard::from_bytes(&format::unwrap(data)?)?;
 
         cg.phase = 0;
         cg.sample_rate = sample_rate;
         cg.cache_invalid = true;
+        cg.optimize = true;
+        cg.dag
+            .node_weights_mut()
+            .for_each(|w| w.init_state(sample_rate));
 
         Ok(cg)
     }
 
-    /// Saves the control graph into postcard-encoded data.
+    /// Saves the control graph into postcard-encoded data, prefixed by its format version.
     pub fn save(&self) -> postcard::Result<Vec<u8>> {
-        postcard::to_stdvec(self)
+        postcard::to_stdvec(self).map(format::wrap)
+    }
+
+    /// Returns a copy of the control graph, with the state of every node reset.
+    pub fn try_clone(&self) -> Result<Self, LoadError> {
+        Self::load(self.sample_rate, &self.save()?)
     }
 
     /// Inserts a node into the control graph.
     ///
     /// Returns the index of the node.
     pub fn insert<N: Node + Send + 'static>(&mut self, n: N) -> NodeIndex {
+        self.insert_boxed(Box::new(n))
+    }
+
+    /// Inserts a node that has already been boxed, such as one parsed from a text patch.
+    ///
+    /// Returns the index of the node.
+    pub fn insert_boxed(&mut self, n: Box<dyn Node>) -> NodeIndex {
         let input_len = n.get_input_labels().len();
-        let node = self.dag.add_node(NodeData {
-            input_arena_ptr: self.node_input_arena.len(),
-            gen: self.phase,
-            val: Sample::default(),
-            node: Box::new(n),
-        });
+        let output_len = n.get_output_labels().len();
+        let node = self.dag.add_node(NodeData::new(
+            n,
+            self.node_input_arena.len(),
+            self.node_output_val_arena.len(),
+            self.phase,
+            self.sample_rate,
+        ));
 
         for _ in 0..input_len {
-            self.node_input_arena.push(0.into());
+            self.node_input_arena.push(0);
             self.node_input_val_arena.push(f64::NAN.into());
         }
 
+        for _ in 0..output_len {
+            self.node_output_val_arena.push(f64::NAN.into());
+        }
+
         for &i in &self.container_stack {
             self.container_members[i].push(node);
         }
@@ -122,14 +302,15 @@ impl ControlGraph {
     /// Returns `None` if the node doesn't exist.
     pub fn remove(&mut self, node: NodeIndex) -> Option<NodeData> {
         self.cache_invalid = true;
+        self.feedback.retain(|f| f.src != node && f.tap != node);
         self.dag.remove_node(node)
     }
 
     /// Disconnects an edge from the control graph.
     ///
-    /// Returns `Some(usize)` if the removal was successful.
+    /// Returns `Some(Edge)` if the removal was successful.
     /// Returns `None` if the edge doesn't exist.
-    pub fn disconnect(&mut self, edge: EdgeIndex) -> Option<usize> {
+    pub fn disconnect(&mut self, edge: EdgeIndex) -> Option<Edge> {
         self.cache_invalid = true;
         self.dag.remove_edge(edge)
     }
@@ -137,138 +318,663 @@ impl ControlGraph {
     /// Traverses the entire control graph beginning at `aout`.
     ///
     /// Returns the next sample.
+    /// Panics if nothing is connected to `aout`. See [ControlGraph::try_next_sample].
     pub fn next_sample(&mut self) -> Sample {
-        let sample = self
-            .update_node(
-                self.dag
-                    .neighbors_directed(self.aout_node, Incoming)
-                    .next()
-                    .unwrap(),
-            )
-            .0;
+        self.try_next_sample().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Traverses the entire control graph beginning at `aout`.
+    ///
+    /// Returns the next sample, or [GraphError::AoutUnconnected] if nothing is connected to `aout`.
+    pub fn try_next_sample(&mut self) -> Result<Sample, GraphError> {
+        if self.cache_invalid {
+            self.cache.clear();
+            self.schedule.clear();
+            self.levels.clear();
+            self.block_len = 0;
+            self.dynamic_slots.clear();
+            self.dynamic_slots
+                .resize(self.node_output_val_arena.len(), false);
+        }
+
+        let (aout_parent, aout_edge) = self
+            .dag
+            .edges_directed(self.aout_node, Incoming)
+            .next()
+            .map(|e| (e.source(), *e.weight()))
+            .ok_or(GraphError::AoutUnconnected)?;
+
+        let set_parent = self.update_node(aout_parent);
+
+        if self.cache_invalid {
+            self.aout_src = set_parent.unwrap_or(
+                self.dag.node_weight(aout_parent).unwrap().output_arena_ptr + aout_edge.src_port,
+            );
+
+            // feedback sources may not lead to `aout`, but still have to be evaluated. When
+            // optimizing, only those whose tap has been evaluated are, which may lead to more taps
+            self.feedback_slots.clear();
+            let mut evaluated = vec![false; self.feedback.len()];
+            loop {
+                let mut progress = false;
+
+                for (i, evaluated) in evaluated.iter_mut().enumerate() {
+                    let FeedbackEdge {
+                        src, src_port, tap, ..
+                    } = self.feedback[i];
+                    let live = !self.optimize || self.dag[tap].gen > self.phase;
+                    if *evaluated || !live {
+                        continue;
+                    }
+
+                    let set_parent = self.update_node(src);
+                    let slot = set_parent
+                        .unwrap_or(self.dag.node_weight(src).unwrap().output_arena_ptr + src_port);
+                    self.feedback_slots.push((slot, tap));
+                    *evaluated = true;
+                    progress = true;
+                }
+
+                if !progress {
+                    break;
+                }
+            }
+        }
+
+        for &(slot, tap) in &self.feedback_slots {
+            let tap = self.dag.node_weight_mut(tap).unwrap();
+            Feedback::push(&mut tap.state, self.node_output_val_arena[slot]);
+        }
 
         self.phase += 1;
         self.cache_invalid = false;
 
-        sample
+        Ok(self.node_output_val_arena[self.aout_src])
     }
 
-    fn update_node(&mut self, node: NodeIndex) -> (Sample, Option<NodeIndex>) {
+    /// Evaluates `node`, or every cached node once the cache is valid.
+    ///
+    /// While rebuilding the cache, container inputs and outputs return the output slot that
+    /// actually feeds them, so that their children can read from it directly.
+    fn update_node(&mut self, node: NodeIndex) -> Option<usize> {
         if self.cache_invalid {
             let mut parents = self.dag.neighbors_directed(node, Incoming).detach();
-            let input_arena_ptr = self.dag.node_weight(node).unwrap().input_arena_ptr;
+            let node_data = self.dag.node_weight(node).unwrap();
+            let input_arena_ptr = node_data.input_arena_ptr;
+            let output_arena_ptr = node_data.output_arena_ptr;
+            let ident = node_data.node.get_ident();
+            let is_container_io = ident == "ContainerInput" || ident == "ContainerOutput";
+            let is_const = ident == "Constant";
+
+            // nodes with several children are only evaluated (and cached) once per traversal
+            if node_data.gen > self.phase {
+                return is_container_io.then(|| self.node_input_arena[input_arena_ptr]);
+            }
+
+            // unconnected ports read from `aout`, rather than a node that may have been removed
+            let inputs = node_data.node.get_input_labels().len();
+            self.node_input_arena[input_arena_ptr..(input_arena_ptr + inputs)].fill(0);
 
-            let mut set_parent = None;
             while let Some((e, n)) = parents.next(&self.dag) {
-                let parent_node = self.dag.node_weight(n).unwrap();
-                let edge_id = *self.dag.edge_weight(e).unwrap();
-                if parent_node.gen <= self.phase {
-                    (_, set_parent) = self.update_node(n);
-                    self.node_input_arena[input_arena_ptr + edge_id] = set_parent.unwrap_or(n);
+                let edge = *self.dag.edge_weight(e).unwrap();
+                let set_parent = self.update_node(n);
+                self.node_input_arena[input_arena_ptr + edge.dest_port] = set_parent
+                    .unwrap_or(self.dag.node_weight(n).unwrap().output_arena_ptr + edge.src_port);
+            }
+
+            // pure nodes with constant inputs only have to be evaluated this once
+            let node_data = &self.dag[node];
+            let folded = self.optimize
+                && node_data.node.is_pure()
+                && self.node_input_arena[input_arena_ptr..(input_arena_ptr + inputs)]
+                    .iter()
+                    .all(|&slot| !self.dynamic_slots[slot]);
+
+            if !is_const && !is_container_io && !folded {
+                let outputs = node_data.node.get_output_labels().len();
+                self.dynamic_slots[output_arena_ptr..(output_arena_ptr + outputs)].fill(true);
+                self.cache.push((node, input_arena_ptr, output_arena_ptr));
+            }
+
+            self.process_node(node, input_arena_ptr, output_arena_ptr);
+            self.dag.node_weight_mut(node).unwrap().gen = self.phase + 1;
+
+            is_container_io.then(|| self.node_input_arena[input_arena_ptr])
+        } else {
+            for i in 0..self.cache.len() {
+                let (node, input_arena_ptr, output_arena_ptr) = self.cache[i];
+                self.process_node(node, input_arena_ptr, output_arena_ptr);
+            }
+
+            None
+        }
+    }
+
+    #[inline(always)]
+    fn process_node(&mut self, node: NodeIndex, input_arena_ptr: usize, output_arena_ptr: usize) {
+        let inputs = update_node_inputs(
+            &self.dag,
+            node,
+            input_arena_ptr,
+            &mut self.node_input_val_arena,
+            &self.node_input_arena,
+            &self.node_output_val_arena,
+        );
+
+        let node = &mut self.dag.node_weight_mut(node).unwrap();
+        let outputs = node.node.get_output_labels().len();
+
+        node.node.process_multi(
+            &self.node_input_val_arena[input_arena_ptr..(input_arena_ptr + inputs)],
+            &mut node.state,
+            &mut self.node_output_val_arena[output_arena_ptr..(output_arena_ptr + outputs)],
+            self.phase,
+            self.sample_rate,
+        );
+    }
+
+    /// Fills `out` with the next `out.len()` samples, running each cached node over the whole
+    /// block at once instead of traversing the graph once per sample.
+    /// Panics if nothing is connected to `aout`. See [ControlGraph::try_process_block].
+    pub fn process_block(&mut self, out: &mut [Sample]) {
+        self.try_process_block(out)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fills `out` with the next `out.len()` samples, running each cached node over the whole
+    /// block at once instead of traversing the graph once per sample.
+    /// Graphs with feedback are processed in blocks no longer than their shortest delay.
+    ///
+    /// Returns [GraphError::AoutUnconnected] if nothing is connected to `aout`.
+    pub fn try_process_block(&mut self, out: &mut [Sample]) -> Result<(), GraphError> {
+        self.try_process_block_with(out, None)
+    }
+
+    /// Like [ControlGraph::process_block], but splits each level of nodes that don't depend on
+    /// each other over the threads of `pool` when the level has enough work to be worth it.
+    /// Produces the same samples regardless of how the work is split.
+    /// Panics if nothing is connected to `aout`. See [ControlGraph::try_process_block_parallel].
+    pub fn process_block_parallel(&mut self, out: &mut [Sample], pool: &ThreadPool) {
+        self.try_process_block_parallel(out, pool)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Like [ControlGraph::try_process_block], but splits each level of nodes that don't depend
+    /// on each other over the threads of `pool` when the level has enough work to be worth it.
+    /// Produces the same samples regardless of how the work is split.
+    ///
+    /// Returns [GraphError::AoutUnconnected] if nothing is connected to `aout`.
+    pub fn try_process_block_parallel(
+        &mut self,
+        out: &mut [Sample],
+        pool: &ThreadPool,
+    ) -> Result<(), GraphError> {
+        self.try_process_block_with(out, Some(pool))
+    }
+
+    fn try_process_block_with(
+        &mut self,
+        out: &mut [Sample],
+        pool: Option<&ThreadPool>,
+    ) -> Result<(), GraphError> {
+        if out.is_empty() {
+            return Ok(());
+        }
+
+        // the cache is only built while traversing the graph sample-by-sample
+        let out = if self.cache_invalid {
+            out[0] = self.try_next_sample()?;
+            &mut out[1..]
+        } else {
+            out
+        };
+
+        if out.is_empty() {
+            return Ok(());
+        }
+
+        let max_len = self
+            .feedback
+            .iter()
+            .map(|f| f.delay)
+            .min()
+            .unwrap_or(out.len());
+
+        match pool {
+            Some(pool) => {
+                if self.levels.is_empty() {
+                    self.build_schedule();
+                }
+
+                for chunk in out.chunks_mut(max_len) {
+                    self.process_chunk_parallel(chunk, pool);
+                }
+            }
+            None => {
+                for chunk in out.chunks_mut(max_len) {
+                    self.process_chunk(chunk);
                 }
             }
+        }
+
+        Ok(())
+    }
 
-            let ident = self.dag.node_weight_mut(node).unwrap().node.get_ident();
+    /// Builds the cache and reserves room for blocks of up to `max_block_len` samples, so that
+    /// processing them doesn't allocate. Building the cache evaluates one sample, which is
+    /// discarded.
+    ///
+    /// Returns [GraphError::AoutUnconnected] if nothing is connected to `aout`.
+    pub fn try_prepare(&mut self, max_block_len: usize) -> Result<(), GraphError> {
+        if self.cache_invalid {
+            self.try_next_sample()?;
+        }
+
+        let max_outputs = self
+            .cache
+            .iter()
+            .map(|&(node, ..)| self.dag[node].node.get_output_labels().len())
+            .max()
+            .unwrap_or(0);
+
+        let reserve = |arena: &mut Vec<Sample>, len: usize| {
+            arena.reserve(len.saturating_sub(arena.len()));
+        };
+        reserve(
+            &mut self.block_arena,
+            self.node_output_val_arena.len() * max_block_len,
+        );
+        reserve(
+            &mut self.node_input_block_arena,
+            self.node_input_arena.len() * max_block_len,
+        );
+        reserve(&mut self.block_scratch, max_outputs * max_block_len);
+
+        if self.levels.is_empty() {
+            self.build_schedule();
+        }
 
-            if ident != "Constant" && ident != "ContainerInput" && ident != "ContainerOutput" {
-                self.cache.push((node, input_arena_ptr));
+        Ok(())
+    }
+
+    /// Sorts the cache into levels, where each node only reads outputs from earlier levels.
+    fn build_schedule(&mut self) {
+        // the cache entry that writes each output slot
+        let mut writers = vec![None; self.node_output_val_arena.len()];
+        for (i, &(node, _, output_arena_ptr)) in self.cache.iter().enumerate() {
+            let outputs = self.dag[node].node.get_output_labels().len();
+            writers[output_arena_ptr..output_arena_ptr + outputs].fill(Some(i));
+        }
+
+        // the cache is in topological order, so each writer's level is known by the time it's read
+        let mut depths = vec![0; self.cache.len()];
+        for (i, &(node, input_arena_ptr, _)) in self.cache.iter().enumerate() {
+            let inputs = self.dag[node].node.get_input_labels().len();
+            depths[i] = self.node_input_arena[input_arena_ptr..input_arena_ptr + inputs]
+                .iter()
+                .filter_map(|&slot| writers[slot])
+                .map(|w| depths[w] + 1)
+                .max()
+                .unwrap_or(0);
+        }
+
+        self.schedule = (0..self.cache.len()).collect();
+        self.schedule.sort_by_key(|&i| depths[i]);
+
+        self.levels.clear();
+        let mut start = 0;
+        for end in 1..=self.schedule.len() {
+            if end == self.schedule.len()
+                || depths[self.schedule[end]] != depths[self.schedule[start]]
+            {
+                self.levels.push(start..end);
+                start = end;
             }
+        }
 
-            let set_parent_out = if ident == "ContainerInput" || ident == "ContainerOutput" {
-                let real_parent = self
-                    .dag
-                    .neighbors_directed(node, Direction::Incoming)
-                    .next()
-                    .unwrap_or(node);
+        let widest = self.levels.iter().map(|l| l.len()).max().unwrap_or(0);
+        self.level_nodes
+            .reserve(widest.saturating_sub(self.level_nodes.len()));
+    }
 
-                let out = set_parent.unwrap_or(real_parent);
+    /// Carries the phase over from `old`, along with the state of every node that has the same
+    /// index and ident in both graphs, such as when this graph is an edited copy of `old`.
+    /// Doesn't allocate, so that graphs can be swapped on the audio thread.
+    pub fn carry_state_from(&mut self, old: &ControlGraph) {
+        self.phase = old.phase;
 
-                Some(out)
-            } else {
-                None
+        for i in old.dag.node_indices() {
+            let (Some(new), Some(old)) = (self.dag.node_weight_mut(i), old.dag.node_weight(i))
+            else {
+                continue;
             };
 
-            let inputs = update_node_inputs(
-                &self.dag,
-                node,
-                input_arena_ptr,
-                &mut self.node_input_val_arena,
-                &mut self.node_input_arena,
-            );
+            if new.node.get_ident() == old.node.get_ident() && new.state.len() == old.state.len() {
+                new.state.copy_from_slice(&old.state);
+            }
+        }
+    }
+
+    /// Runs each cached node over `out.len()` samples, which must not be longer than the delay
+    /// of any feedback connection.
+    fn process_chunk(&mut self, out: &mut [Sample]) {
+        let len = out.len();
+        self.resize_block(len);
+
+        for i in 0..self.cache.len() {
+            self.process_cached_block(i, len);
+        }
+
+        self.finish_chunk(out);
+    }
+
+    /// Like [ControlGraph::process_chunk], but runs the nodes of each level on the threads of
+    /// `pool` if the level has enough work.
+    fn process_chunk_parallel(&mut self, out: &mut [Sample], pool: &ThreadPool) {
+        let len = out.len();
+        self.resize_block(len);
+
+        for l in 0..self.levels.len() {
+            let level = self.levels[l].clone();
+
+            // nodes with several outputs share `block_scratch`, so they stay on this thread
+            self.level_nodes.clear();
+            for &i in &self.schedule[level.clone()] {
+                let (node, input_arena_ptr, output_arena_ptr) = self.cache[i];
+                let data = &mut self.dag[node];
+                if data.node.get_output_labels().len() == 1 {
+                    self.level_nodes.push(LevelNode {
+                        data,
+                        input_arena_ptr,
+                        output_arena_ptr,
+                    });
+                }
+            }
 
-            let node = &mut self.dag.node_weight_mut(node).unwrap();
+            let parallel = pool.workers() > 0
+                && self.level_nodes.len() > 1
+                && self.level_nodes.len() * len >= PARALLEL_MIN_WORK;
 
-            let val = node.node.process(
-                &self.node_input_val_arena[input_arena_ptr..(input_arena_ptr + inputs)],
+            if !parallel {
+                for s in level {
+                    self.process_cached_block(self.schedule[s], len);
+                }
+
+                continue;
+            }
+
+            let arenas = BlockArenas {
+                outputs: self.block_arena.as_mut_ptr(),
+                inputs: self.node_input_block_arena.as_mut_ptr(),
+                len,
+            };
+            let (phase, sample_rate) = (self.phase, self.sample_rate);
+            let (nodes, input_slots) = (&self.level_nodes, &self.node_input_arena);
+
+            pool.run(nodes.len(), &|n| {
+                // SAFETY: see `LevelNode` and `BlockArenas`. Each task gets a different node
+                unsafe { process_level_node(nodes[n], input_slots, arenas, phase, sample_rate) }
+            });
+
+            for n in 0..self.level_nodes.len() {
+                let slot = self.level_nodes[n].output_arena_ptr;
+                self.node_output_val_arena[slot] = self.block_arena[(slot + 1) * len - 1];
+            }
+
+            for s in level {
+                let i = self.schedule[s];
+                if self.dag[self.cache[i].0].node.get_output_labels().len() != 1 {
+                    self.process_cached_block(i, len);
+                }
+            }
+        }
+
+        self.finish_chunk(out);
+    }
+
+    /// Sizes the block arenas for chunks of `len` samples.
+    fn resize_block(&mut self, len: usize) {
+        if len != self.block_len {
+            self.block_len = len;
+            self.block_arena
+                .resize(self.node_output_val_arena.len() * len, Sample::default());
+            self.node_input_block_arena
+                .resize(self.node_input_arena.len() * len, Sample::default());
+
+            // uncached nodes (constants) hold the same value for the entire block
+            for (slot, val) in self.node_output_val_arena.iter().enumerate() {
+                self.block_arena[slot * len..(slot + 1) * len].fill(*val);
+            }
+        }
+    }
+
+    /// Runs `cache[i]` over a chunk of `len` samples.
+    fn process_cached_block(&mut self, i: usize, len: usize) {
+        let (node, input_arena_ptr, output_arena_ptr) = self.cache[i];
+        let inputs = update_node_input_block(
+            &self.dag,
+            node,
+            input_arena_ptr,
+            len,
+            &self.block_arena,
+            &mut self.node_input_block_arena,
+            &self.node_input_arena,
+        );
+
+        let node = &mut self.dag.node_weight_mut(node).unwrap();
+        let outputs = node.node.get_output_labels().len();
+        let input_block =
+            &self.node_input_block_arena[input_arena_ptr * len..(input_arena_ptr + inputs) * len];
+
+        if outputs == 1 {
+            node.node.process_block(
+                input_block,
+                &mut node.state,
+                &mut self.block_arena[output_arena_ptr * len..(output_arena_ptr + 1) * len],
+                self.phase,
+                self.sample_rate,
+            );
+        } else {
+            self.block_scratch.resize(outputs * len, Sample::default());
+            node.node.process_block(
+                input_block,
+                &mut node.state,
+                &mut self.block_scratch,
                 self.phase,
                 self.sample_rate,
             );
 
-            node.val = val;
+            for (frame, vals) in self.block_scratch.chunks_exact(outputs).enumerate() {
+                for (o, val) in vals.iter().enumerate() {
+                    self.block_arena[(output_arena_ptr + o) * len + frame] = *val;
+                }
+            }
+        }
 
-            (val, set_parent_out)
-        } else {
-            let mut val = Sample::default();
-            for (node, input_arena_ptr) in &self.cache {
-                let inputs = update_node_inputs(
-                    &self.dag,
-                    *node,
-                    *input_arena_ptr,
-                    &mut self.node_input_val_arena,
-                    &mut self.node_input_arena,
-                );
+        for o in 0..outputs {
+            self.node_output_val_arena[output_arena_ptr + o] =
+                self.block_arena[(output_arena_ptr + o + 1) * len - 1];
+        }
+    }
 
-                let node = &mut self.dag.node_weight_mut(*node).unwrap();
-                val = node.node.process(
-                    &self.node_input_val_arena[*input_arena_ptr..(input_arena_ptr + inputs)],
-                    self.phase,
-                    self.sample_rate,
-                );
+    /// Pushes the chunk into feedback taps, copies the output routed to `aout` into `out` and
+    /// advances the phase.
+    fn finish_chunk(&mut self, out: &mut [Sample]) {
+        let len = out.len();
 
-                node.val = val;
+        for &(slot, tap) in &self.feedback_slots {
+            let tap = self.dag.node_weight_mut(tap).unwrap();
+            for val in &self.block_arena[slot * len..(slot + 1) * len] {
+                Feedback::push(&mut tap.state, *val);
             }
+        }
 
-            (val, None)
+        let src = self.aout_src;
+        out.copy_from_slice(&self.block_arena[src * len..(src + 1) * len]);
+
+        self.phase += len as u64;
+    }
+
+    /// Sets the value of every node that outputs `source`, such as [Gate].
+    pub fn set_source(&mut self, source: Source, value: Sample) {
+        for w in self.dag.node_weights_mut() {
+            if w.node.get_source() == Some(source) {
+                w.state[0] = value;
+            }
         }
     }
 
+    /// Sets the [Source::Param] of macro `n` from its `normalized` value, mapping it into the
+    /// macro's range.
+    pub fn set_macro_value(&mut self, n: usize, normalized: f64) {
+        let value = self.macros[n].map(normalized);
+        self.set_source(Source::Param(n as u8), Sample::mono(value));
+    }
+
+    /// Returns the description of macro `n`, which must be less than [MACRO_COUNT].
+    pub fn get_macro(&self, n: usize) -> &Macro {
+        &self.macros[n]
+    }
+
+    /// Describes macro `n`, which must be less than [MACRO_COUNT].
+    pub fn set_macro(&mut self, n: usize, desc: Macro) {
+        self.macros[n] = desc;
+    }
+
+    /// Sets the phase of the control graph, resetting the state of every node.
     pub fn set_phase(&mut self, phase: u64) {
         self.phase = phase;
-        self.dag.node_weights_mut().for_each(|w| w.gen = 0);
+        self.dag.node_weights_mut().for_each(|w| {
+            w.gen = 0;
+            w.node.reset_state(&mut w.state);
+        });
     }
 
     pub fn reset_phase(&mut self) {
         self.set_phase(0);
     }
 
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Sets the sample rate of the control graph, reallocating the state of nodes whose size
+    /// depends on it.
     pub fn set_sample_rate(&mut self, sample_rate: u32) {
         self.sample_rate = sample_rate;
+        self.dag.node_weights_mut().for_each(|w| {
+            if w.state.len() != w.node.state_len(sample_rate) {
+                w.init_state(sample_rate);
+            }
+        });
+    }
+
+    /// Sets whether the graph is optimized before it's evaluated, which it is by default.
+    /// Optimizing doesn't change the output. It:
+    ///
+    /// - Evaluates [pure](Node::is_pure) nodes with only constant inputs once, instead of on every
+    ///   sample.
+    /// - Skips feedback loops that can't reach `aout`.
+    /// - Fuses the negation and sum of a [Sub](crate::container::Sub) container into a single
+    ///   [Instruction::Sub] when the graph is [compiled](ControlGraph::compile).
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+        self.cache_invalid = true;
+    }
+
+    /// Sets what happens when connecting to an input port that is already connected.
+    pub fn set_port_merge(&mut self, port_merge: PortMerge) {
+        self.port_merge = port_merge;
     }
 
     /// Returns the neighbors of the specified node.
     pub fn get_node_neighbors(&self, node: NodeIndex, direction: Direction) -> Vec<Neighbor> {
         self.dag
             .edges_directed(node, direction)
-            .map(|e| (e.id(), e.target()))
-            .map(|(e, n)| Neighbor {
-                node_index: n,
-                edge_index: e,
-                dest_port_id: *self.dag.edge_weight(e).unwrap(),
+            .map(|e| Neighbor {
+                node_index: match direction {
+                    Outgoing => e.target(),
+                    Incoming => e.source(),
+                },
+                edge_index: e.id(),
+                src_port_id: e.weight().src_port,
+                dest_port_id: e.weight().dest_port,
             })
             .collect::<Vec<_>>()
     }
 
+    /// Returns the index of `aout`, which represents the final node in the graph.
+    pub fn get_aout_node(&self) -> NodeIndex {
+        self.aout_node
+    }
+
+    /// Returns every feedback connection in the control graph.
+    pub fn get_feedback_edges(&self) -> &[FeedbackEdge] {
+        &self.feedback
+    }
+
     /// Returns all node indexes contained in the control graph.
-    pub fn get_node_indexes(&self) -> NodeIndices<NodeData, u32> {
+    pub fn get_node_indexes(&self) -> NodeIndices<'_, NodeData, u32> {
         self.dag.node_indices()
     }
 
+    /// Panics if the node doesn't exist. See [ControlGraph::try_get_node].
     pub fn get_node(&self, id: NodeIndex) -> &dyn Node {
-        self.dag.node_weight(id).unwrap().node.as_ref()
+        self.try_get_node(id).unwrap_or_else(|e| panic!("{e}"))
     }
 
+    pub fn try_get_node(&self, id: NodeIndex) -> Result<&dyn Node, GraphError> {
+        self.dag
+            .node_weight(id)
+            .map(|w| w.node.as_ref())
+            .ok_or(GraphError::NoSuchNode(id))
+    }
+
+    /// Returns the port number of the input of `node` labeled `label`.
+    pub fn try_get_input_port(&self, node: NodeIndex, label: &str) -> Result<usize, GraphError> {
+        let labels = self.try_get_node(node)?.get_input_labels();
+
+        labels
+            .iter()
+            .position(|l| l == label)
+            .ok_or_else(|| GraphError::NoSuchPort {
+                node,
+                label: label.into(),
+                valid: labels.iter().map(|l| l.to_string()).collect(),
+            })
+    }
+
+    /// Returns the port number of the output of `node` labeled `label`.
+    pub fn try_get_output_port(&self, node: NodeIndex, label: &str) -> Result<usize, GraphError> {
+        let labels = self.try_get_node(node)?.get_output_labels();
+
+        labels
+            .iter()
+            .position(|l| l == label)
+            .ok_or_else(|| GraphError::NoSuchPort {
+                node,
+                label: label.into(),
+                valid: labels.iter().map(|l| l.to_string()).collect(),
+            })
+    }
+
+    /// Returns the value of the first output of the node.
+    /// Panics if the node doesn't exist. See [ControlGraph::try_get_node_val].
     pub fn get_node_val(&self, id: NodeIndex) -> Sample {
-        self.dag.node_weight(id).unwrap().val
+        self.try_get_node_val(id).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Returns the value of the first output of the node.
+    pub fn try_get_node_val(&self, id: NodeIndex) -> Result<Sample, GraphError> {
+        Ok(self.try_get_node_vals(id)?[0])
+    }
+
+    /// Returns the values of every output of the node.
+    pub fn try_get_node_vals(&self, id: NodeIndex) -> Result<&[Sample], GraphError> {
+        let w = self.dag.node_weight(id).ok_or(GraphError::NoSuchNode(id))?;
+        let outputs = w.node.get_output_labels().len();
+
+        Ok(&self.node_output_val_arena[w.output_arena_ptr..(w.output_arena_ptr + outputs)])
     }
 
     fn push_container_layer(&mut self) {
@@ -285,7 +991,7 @@ impl ControlGraph {
         &self.container_children
     }
 
-    pub fn get_container_member_indexes(&self, i: usize) -> std::slice::Iter<NodeIndex> {
+    pub fn get_container_member_indexes(&self, i: usize) -> std::slice::Iter<'_, NodeIndex> {
         self.container_members[i].iter()
     }
 
@@ -293,10 +999,50 @@ impl ControlGraph {
         &self.container_idents[i]
     }
 
+    /// Returns the number of containers that have been inserted.
+    pub fn get_container_count(&self) -> usize {
+        self.container_idents.len()
+    }
+
+    /// Returns the container that container `i` is nested in, if any.
+    pub fn get_container_parent(&self, i: usize) -> Option<usize> {
+        self.container_children
+            .iter()
+            .position(|children| children.contains(&i))
+            .and_then(|p| p.checked_sub(1))
+    }
+
+    /// Adds an empty container labeled `ident`, nested in `parent` if there is one.
+    /// Unlike [ControlGraph::insert_container], this doesn't construct any members.
+    ///
+    /// Returns the index of the container.
+    pub(crate) fn add_container(&mut self, ident: &str, parent: Option<usize>) -> usize {
+        let i = self.container_members.len();
+
+        self.container_children[parent.map(|p| p + 1).unwrap_or_default()].push(i);
+        self.container_idents.push(ident.into());
+        self.container_members.push(vec![]);
+        self.container_children.push(vec![]);
+
+        i
+    }
+
+    /// Adds `node` to container `i`, along with every container that it's nested in.
+    pub(crate) fn add_container_member(&mut self, i: usize, node: NodeIndex) {
+        let mut container = Some(i);
+        while let Some(i) = container {
+            self.container_members[i].push(node);
+            container = self.get_container_parent(i);
+        }
+    }
+
+    /// Inserts a container into the control graph, constructing its members.
+    ///
+    /// Returns the container's (`inputs`, `outputs`).
     pub fn insert_container<C: Container>(
         &mut self,
         container: C,
-    ) -> (Vec<NodeIndex>, Vec<NodeIndex>) {
+    ) -> (ContainerPorts, ContainerPorts) {
         let parent = self
             .container_stack
             .last()
@@ -325,7 +1071,10 @@ impl ControlGraph {
 
         self.pop_container_layer();
 
-        (inputs, outputs)
+        (
+            ContainerPorts::new(inputs, input_labels),
+            ContainerPorts::new(outputs, output_labels),
+        )
     }
 }
 
@@ -340,16 +1089,142 @@ impl ControlGraph {
 
     /// Connects an existing node (`src`) into another existing node (`dest`).
     /// `dest_port` determines the the port number of `dest` that `src` will connect to.
+    /// Panics if the connection is invalid. See [ControlGraph::try_connect].
     pub fn connect_ex_ex_port(&mut self, src: NodeIndex, dest: NodeIndex, dest_port: usize) {
+        self.try_connect(src, dest, dest_port)
+            .unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    /// Connects an existing node (`src`) into another existing node (`dest`).
+    /// `dest_port` determines the the port number of `dest` that `src` will connect to.
+    ///
+    /// Returns the index of the new edge. See [ControlGraph::try_connect_ports].
+    pub fn try_connect(
+        &mut self,
+        src: NodeIndex,
+        dest: NodeIndex,
+        dest_port: usize,
+    ) -> Result<EdgeIndex, GraphError> {
+        self.try_connect_ports(src, 0, dest, dest_port)
+    }
+
+    /// Connects output `src_port` of an existing node (`src`) into input `dest_port` of another
+    /// existing node (`dest`).
+    /// Panics if the connection is invalid. See [ControlGraph::try_connect_ports].
+    pub fn connect_ports(
+        &mut self,
+        src: NodeIndex,
+        src_port: usize,
+        dest: NodeIndex,
+        dest_port: usize,
+    ) {
+        self.try_connect_ports(src, src_port, dest, dest_port)
+            .unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    /// Connects output `src_port` of an existing node (`src`) into input `dest_port` of another
+    /// existing node (`dest`).
+    ///
+    /// Returns the index of the new edge, or an error if either node doesn't exist, either port
+    /// is out of range, or the edge would cause a cycle.
+    ///
+    /// If `dest_port` is already connected, the [PortMerge] set by
+    /// [ControlGraph::set_port_merge] decides whether this fails or sums both edges. When summed,
+    /// the returned edge connects `src` to the inserted [Add] node.
+    pub fn try_connect_ports(
+        &mut self,
+        src: NodeIndex,
+        src_port: usize,
+        dest: NodeIndex,
+        dest_port: usize,
+    ) -> Result<EdgeIndex, GraphError> {
+        for node in [src, dest] {
+            if !self.dag.contains_node(node) {
+                return Err(GraphError::NoSuchNode(node));
+            }
+        }
+
+        let src_ports = self.get_node(src).get_output_labels().len();
+
+        if src_port >= src_ports {
+            return Err(GraphError::OutputOutOfRange {
+                node: src,
+                port: src_port,
+                len: src_ports,
+            });
+        }
+
+        // `aout` is an `Empty` node, but still takes a single input
+        let ports = if dest == self.aout_node {
+            1
+        } else {
+            self.get_node(dest).get_input_labels().len()
+        };
+
+        if dest_port >= ports {
+            return Err(GraphError::PortOutOfRange {
+                node: dest,
+                port: dest_port,
+                len: ports,
+            });
+        }
+
         if would_cycle(&self.dag, src, dest, &mut self.dag_cycle_state) {
-            panic!(
-                "Adding edge {src:?} -> {dest:?} would cause a cycle!\n\nGraph:{:?}",
-                self.dag
-            );
+            return Err(GraphError::WouldCycle { src, dest });
         }
 
+        let occupied = self
+            .dag
+            .edges_directed(dest, Incoming)
+            .find(|e| e.weight().dest_port == dest_port)
+            .map(|e| (e.id(), e.source(), e.weight().src_port));
+
         self.cache_invalid = true;
-        self.dag.add_edge(src, dest, dest_port);
+
+        match (occupied, self.port_merge) {
+            (None, _) => Ok(self.dag.add_edge(
+                src,
+                dest,
+                Edge {
+                    src_port,
+                    dest_port,
+                },
+            )),
+            (Some(_), PortMerge::Reject) => Err(GraphError::PortOccupied {
+                node: dest,
+                port: dest_port,
+            }),
+            (Some((edge, existing, existing_port)), PortMerge::Sum) => {
+                self.dag.remove_edge(edge);
+
+                let add = self.insert(Add);
+                self.dag.add_edge(
+                    existing,
+                    add,
+                    Edge {
+                        src_port: existing_port,
+                        dest_port: 0,
+                    },
+                );
+                self.dag.add_edge(
+                    add,
+                    dest,
+                    Edge {
+                        src_port: 0,
+                        dest_port,
+                    },
+                );
+
+                Ok(self.dag.add_edge(
+                    src,
+                    add,
+                    Edge {
+                        src_port,
+                        dest_port: 1,
+                    },
+                ))
+            }
+        }
     }
     /// Connects an existing node (`src`) into another existing node (`dest`).
     /// `dest_port` determines the the port number of `dest` that `src` will connect to.
@@ -358,11 +1233,160 @@ impl ControlGraph {
         self.connect_ex_ex_port(src, dest, dest_port)
     }
 
+    /// Feeds output `src_port` of `src` back into input `dest_port` of `dest`, `delay` samples
+    /// later.
+    /// Panics if the connection is invalid. See [ControlGraph::try_connect_feedback].
+    pub fn connect_feedback(
+        &mut self,
+        src: NodeIndex,
+        src_port: usize,
+        dest: NodeIndex,
+        dest_port: usize,
+        delay: usize,
+    ) -> NodeIndex {
+        self.try_connect_feedback(src, src_port, dest, dest_port, delay)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Feeds output `src_port` of `src` back into input `dest_port` of `dest`, `delay` samples
+    /// later. Unlike [ControlGraph::try_connect_ports], `dest` may lead to `src`.
+    ///
+    /// The connection goes through a new [Feedback] node, which is connected to `dest` like any
+    /// other node. Returns the index of the [Feedback] node, or
+    /// [GraphError::FeedbackWithoutDelay] if `delay` is 0.
+    pub fn try_connect_feedback(
+        &mut self,
+        src: NodeIndex,
+        src_port: usize,
+        dest: NodeIndex,
+        dest_port: usize,
+        delay: usize,
+    ) -> Result<NodeIndex, GraphError> {
+        if delay == 0 {
+            return Err(GraphError::FeedbackWithoutDelay { src, dest });
+        }
+
+        let tap = self.insert(Feedback { delay });
+
+        if let Err(e) = self
+            .try_connect(tap, dest, dest_port)
+            .and_then(|_| self.try_connect_feedback_tap(src, src_port, tap))
+        {
+            self.remove(tap);
+            return Err(e);
+        }
+
+        Ok(tap)
+    }
+
+    /// Feeds output `src_port` of `src` back through `tap`, which must be a [Feedback] node.
+    pub(crate) fn try_connect_feedback_tap(
+        &mut self,
+        src: NodeIndex,
+        src_port: usize,
+        tap: NodeIndex,
+    ) -> Result<(), GraphError> {
+        let src_ports = self.try_get_node(src)?.get_output_labels().len();
+
+        if src_port >= src_ports {
+            return Err(GraphError::OutputOutOfRange {
+                node: src,
+                port: src_port,
+                len: src_ports,
+            });
+        }
+
+        // the state of a `Feedback` node holds its write position, then its buffer
+        let delay = self.try_get_node(tap)?.state_len(self.sample_rate) - 1;
+
+        self.cache_invalid = true;
+        self.feedback.push(FeedbackEdge {
+            src,
+            src_port,
+            tap,
+            delay,
+        });
+
+        Ok(())
+    }
+
+    /// Connects an existing node (`src`) into the input port of `dest` labeled `label`.
+    /// Panics if the connection is invalid. See [ControlGraph::try_connect_named].
+    pub fn connect_named(&mut self, src: NodeIndex, dest: NodeIndex, label: &str) {
+        self.try_connect_named(src, dest, label)
+            .unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    /// Connects an existing node (`src`) into the input port of `dest` labeled `label`.
+    ///
+    /// Returns the index of the new edge, or [GraphError::NoSuchPort] if `dest` has no input
+    /// labeled `label`. See [ControlGraph::try_connect].
+    pub fn try_connect_named(
+        &mut self,
+        src: NodeIndex,
+        dest: NodeIndex,
+        label: &str,
+    ) -> Result<EdgeIndex, GraphError> {
+        let dest_port = self.try_get_input_port(dest, label)?;
+        self.try_connect(src, dest, dest_port)
+    }
+
+    /// Connects the output of `src` labeled `src_label` into the input of `dest` labeled
+    /// `dest_label`.
+    /// Panics if the connection is invalid. See [ControlGraph::try_connect_labels].
+    pub fn connect_labels(
+        &mut self,
+        src: NodeIndex,
+        src_label: &str,
+        dest: NodeIndex,
+        dest_label: &str,
+    ) {
+        self.try_connect_labels(src, src_label, dest, dest_label)
+            .unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    /// Connects the output of `src` labeled `src_label` into the input of `dest` labeled
+    /// `dest_label`.
+    ///
+    /// Returns the index of the new edge, or [GraphError::NoSuchPort] if either label doesn't
+    /// exist. See [ControlGraph::try_connect_ports].
+    pub fn try_connect_labels(
+        &mut self,
+        src: NodeIndex,
+        src_label: &str,
+        dest: NodeIndex,
+        dest_label: &str,
+    ) -> Result<EdgeIndex, GraphError> {
+        let src_port = self.try_get_output_port(src, src_label)?;
+        let dest_port = self.try_get_input_port(dest, dest_label)?;
+        self.try_connect_ports(src, src_port, dest, dest_port)
+    }
+
     /// Connects a node to `aout`, which represents the final node in the graph.
     pub fn connect_ex_aout(&mut self, a: NodeIndex) {
         self.connect_ex_ex_port(a, self.aout_node, 0);
     }
 
+    /// Connects a node to `aout`, which represents the final node in the graph.
+    /// See [ControlGraph::try_connect].
+    pub fn try_connect_ex_aout(&mut self, a: NodeIndex) -> Result<EdgeIndex, GraphError> {
+        self.try_connect(a, self.aout_node, 0)
+    }
+
+    /// Connects many existing nodes (`srcs`) into another existing node (`dest`).
+    /// `srcs[0]` will connect to port 0 of `dest`, `srcs[1]` will connect to port 1, etc.
+    /// See [ControlGraph::try_connect].
+    pub fn try_connect_many_ex(
+        &mut self,
+        srcs: &[NodeIndex],
+        dest: NodeIndex,
+    ) -> Result<Vec<EdgeIndex>, GraphError> {
+        srcs.iter()
+            .enumerate()
+            .map(|(i, src)| self.try_connect(*src, dest, i))
+            .collect()
+    }
+
     /// Connects many existing nodes (`srcs`) into another existing node (`dest`).
     /// `srcs[0]` will connect to port 0 of `dest`, `srcs[1]` will connect to port 1, etc.
     pub fn connect_many_ex(&mut self, srcs: &[NodeIndex], dest: NodeIndex) {
@@ -523,24 +1547,82 @@ impl ControlGraph {
 
 #[inline(always)]
 fn update_node_inputs(
-    dag: &StableDiGraph<NodeData, usize, u32>,
+    dag: &StableDiGraph<NodeData, Edge, u32>,
     node: NodeIndex,
     input_arena_ptr: usize,
     input_val_arena: &mut [Sample],
-    input_arena: &mut [NodeIndex],
+    input_arena: &[usize],
+    output_val_arena: &[Sample],
+) -> usize {
+    let node = &dag.node_weight(node).unwrap().node;
+    let inputs = node.get_input_labels().len();
+
+    for i in 0..inputs {
+        input_val_arena[input_arena_ptr + i] = output_val_arena[input_arena[input_arena_ptr + i]];
+    }
+
+    inputs
+}
+
+#[inline(always)]
+fn update_node_input_block(
+    dag: &StableDiGraph<NodeData, Edge, u32>,
+    node: NodeIndex,
+    input_arena_ptr: usize,
+    len: usize,
+    block_arena: &[Sample],
+    input_block_arena: &mut [Sample],
+    input_arena: &[usize],
 ) -> usize {
     let node = &dag.node_weight(node).unwrap().node;
     let inputs = node.get_input_labels().len();
+    let input_block =
+        &mut input_block_arena[input_arena_ptr * len..(input_arena_ptr + inputs) * len];
 
     for i in 0..inputs {
-        input_val_arena[input_arena_ptr + i] = dag
-            .node_weight(input_arena[input_arena_ptr + i])
-            .unwrap()
-            .val;
+        let src = input_arena[input_arena_ptr + i];
+        for (frame, val) in block_arena[src * len..(src + 1) * len].iter().enumerate() {
+            input_block[frame * inputs + i] = *val;
+        }
     }
 
     inputs
 }
+
+/// Runs a node with a single output over a chunk, like [ControlGraph::process_cached_block], on
+/// any thread.
+///
+/// # Safety
+///
+/// No other thread may access the node or its input and output slots while it runs, and the
+/// output slots that it reads from mustn't be written to.
+unsafe fn process_level_node(
+    node: LevelNode,
+    input_slots: &[usize],
+    arenas: BlockArenas,
+    phase: u64,
+    sample_rate: u32,
+) {
+    let data = &mut *node.data;
+    let len = arenas.len;
+    let inputs = data.node.get_input_labels().len();
+    let input_block =
+        std::slice::from_raw_parts_mut(arenas.inputs.add(node.input_arena_ptr * len), inputs * len);
+
+    for i in 0..inputs {
+        let src = input_slots[node.input_arena_ptr + i];
+        let src = std::slice::from_raw_parts(arenas.outputs.add(src * len), len);
+        for (frame, val) in src.iter().enumerate() {
+            input_block[frame * inputs + i] = *val;
+        }
+    }
+
+    let outputs =
+        std::slice::from_raw_parts_mut(arenas.outputs.add(node.output_arena_ptr * len), len);
+    data.node
+        .process_block(input_block, &mut data.state, outputs, phase, sample_rate);
+}
+
 #[inline(always)]
 fn would_cycle<N, E, Ix: IndexType>(
     dag: &StableDiGraph<N, E, Ix>,
@@ -0,0 +1,295 @@
+//! The versioned envelope written by [ControlGraph::save], and the migrations that upgrade older
+//! payloads to the current layout.
+//!
+//! Saved data begins with [MAGIC], then [FORMAT_VERSION] as a little-endian `u32`, then the
+//! postcard-encoded control graph. Postcard isn't self-describing, so any change to the
+//! serialized fields of [ControlGraph], [NodeData] or a node needs a new version:
+//!
+//! 1. Freeze the new layout in a module like [v1]. Migrations build these frozen layouts rather
+//!    than the live [ControlGraph], so that they keep producing the same data once it changes.
+//! 2. Bump [FORMAT_VERSION].
+//! 3. Append a migration from the previous frozen layout to [MIGRATIONS].
+//! 4. Save a fixture in the old version for `tests::load_fixtures`, and what the migration makes
+//!    of it for `tests::migrations_are_frozen`.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::error::LoadError;
+
+#[cfg(doc)]
+use super::{ControlGraph, NodeData};
+
+/// Marks data written by [ControlGraph::save]. Data without it was saved before the format was
+/// versioned, and is read as version 0.
+pub(super) const MAGIC: [u8; 4] = *b"DGRD";
+
+/// The version of the layout written by [ControlGraph::save].
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Upgrades a payload by one version.
+type Migration = fn(&[u8]) -> Result<Vec<u8>, LoadError>;
+
+/// Migration `i` upgrades a payload from version `i` to `i + 1`.
+const MIGRATIONS: [Migration; FORMAT_VERSION as usize] = [v0_to_v1];
+
+/// Wraps a postcard payload of the current layout in the envelope.
+pub(super) fn wrap(payload: Vec<u8>) -> Vec<u8> {
+    let mut data = Vec::with_capacity(8 + payload.len());
+    data.extend(MAGIC);
+    data.extend(FORMAT_VERSION.to_le_bytes());
+    data.extend(payload);
+
+    data
+}
+
+/// Unwraps the envelope, migrating the payload to the current layout one version at a time.
+pub(super) fn unwrap(data: &[u8]) -> Result<Cow<'_, [u8]>, LoadError> {
+    let (version, payload) = match data.strip_prefix(&MAGIC) {
+        Some(rest) if rest.len() >= 4 => {
+            let (version, payload) = rest.split_at(4);
+            (u32::from_le_bytes(version.try_into().unwrap()), payload)
+        }
+        Some(_) => return Err(postcard::Error::DeserializeUnexpectedEnd.into()),
+        None => (0, data),
+    };
+
+    if version > FORMAT_VERSION {
+        return Err(LoadError::NewerVersion(version));
+    }
+
+    let mut payload = Cow::Borrowed(payload);
+    for migration in &MIGRATIONS[version as usize..] {
+        payload = Cow::Owned(migration(&payload)?);
+    }
+
+    Ok(payload)
+}
+
+/// The layout before the format was versioned, when every node had a single output and edges
+/// were weighted by their input port.
+mod v0 {
+    use petgraph::graph::NodeIndex;
+    use petgraph::stable_graph::StableDiGraph;
+    use serde::Deserialize;
+
+    use crate::node::Node;
+    use crate::Sample;
+
+    #[derive(Deserialize)]
+    pub struct NodeData {
+        pub _input_arena_ptr: usize,
+        pub node: Box<dyn Node>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct ControlGraph {
+        pub sample_rate: u32,
+        pub dag: StableDiGraph<NodeData, usize, u32>,
+        pub _node_input_arena: Vec<NodeIndex>,
+        pub _node_input_val_arena: Vec<Sample>,
+        pub container_idents: Vec<String>,
+        pub container_stack: Vec<usize>,
+        pub container_members: Vec<Vec<NodeIndex>>,
+        pub container_children: Vec<Vec<usize>>,
+        pub aout_node: NodeIndex,
+    }
+}
+
+/// The first versioned layout, which added multiple outputs per node, feedback edges and macros.
+mod v1 {
+    use petgraph::graph::NodeIndex;
+    use petgraph::stable_graph::StableDiGraph;
+    use petgraph::Direction::Incoming;
+    use serde::Serialize;
+
+    use crate::error::GraphError;
+    use crate::node::{Empty, Node};
+    use crate::Sample;
+
+    #[derive(Serialize)]
+    pub struct NodeData {
+        pub input_arena_ptr: usize,
+        pub output_arena_ptr: usize,
+        pub node: Box<dyn Node>,
+    }
+
+    #[derive(Serialize)]
+    pub struct Edge {
+        pub src_port: usize,
+        pub dest_port: usize,
+    }
+
+    #[derive(Serialize)]
+    pub struct FeedbackEdge {
+        pub src: NodeIndex,
+        pub src_port: usize,
+        pub tap: NodeIndex,
+        pub delay: usize,
+    }
+
+    #[derive(Serialize)]
+    pub struct Macro {
+        pub name: String,
+        pub min: f64,
+        pub max: f64,
+        pub unit: String,
+    }
+
+    #[derive(Serialize)]
+    pub struct ControlGraph {
+        pub sample_rate: u32,
+        pub dag: StableDiGraph<NodeData, Edge, u32>,
+        pub node_input_arena: Vec<usize>,
+        pub node_input_val_arena: Vec<Sample>,
+        pub node_output_val_arena: Vec<Sample>,
+        pub container_idents: Vec<String>,
+        pub container_stack: Vec<usize>,
+        pub container_members: Vec<Vec<NodeIndex>>,
+        pub container_children: Vec<Vec<usize>>,
+        pub aout_node: NodeIndex,
+        pub feedback: Vec<FeedbackEdge>,
+        pub macros: Vec<Macro>,
+    }
+
+    impl ControlGraph {
+        /// Returns an empty graph, laid out like a new live graph was in version 1.
+        pub fn new(sample_rate: u32) -> Self {
+            let mut dag = StableDiGraph::new();
+            let aout_node = dag.add_node(NodeData {
+                input_arena_ptr: 0,
+                output_arena_ptr: 0,
+                node: Box::new(Empty),
+            });
+
+            Self {
+                sample_rate,
+                dag,
+                node_input_arena: vec![0],
+                node_input_val_arena: vec![f64::NAN.into()],
+                node_output_val_arena: vec![f64::NAN.into()],
+                container_idents: vec![],
+                container_stack: vec![],
+                container_members: vec![],
+                container_children: vec![vec![]],
+                aout_node,
+                feedback: vec![],
+                macros: (0..16)
+                    .map(|_| Macro {
+                        name: String::new(),
+                        min: 0.0,
+                        max: 1.0,
+                        unit: String::new(),
+                    })
+                    .collect(),
+            }
+        }
+
+        /// Inserts a node after the ones already in the arenas.
+        pub fn insert(&mut self, node: Box<dyn Node>) -> NodeIndex {
+            let input_len = node.get_input_labels().len();
+            let output_len = node.get_output_labels().len();
+            let i = self.dag.add_node(NodeData {
+                input_arena_ptr: self.node_input_arena.len(),
+                output_arena_ptr: self.node_output_val_arena.len(),
+                node,
+            });
+
+            self.node_input_arena.extend((0..input_len).map(|_| 0));
+            self.node_input_val_arena
+                .extend((0..input_len).map(|_| Sample::from(f64::NAN)));
+            self.node_output_val_arena
+                .extend((0..output_len).map(|_| Sample::from(f64::NAN)));
+
+            i
+        }
+
+        /// Connects output 0 of `src` into input `dest_port` of `dest`, which must be free.
+        pub fn connect(
+            &mut self,
+            src: NodeIndex,
+            dest: NodeIndex,
+            dest_port: usize,
+        ) -> Result<(), GraphError> {
+            // `aout` is an `Empty` node, but still takes a single input
+            let len = if dest == self.aout_node {
+                1
+            } else {
+                self.dag[dest].node.get_input_labels().len()
+            };
+
+            if dest_port >= len {
+                return Err(GraphError::PortOutOfRange {
+                    node: dest,
+                    port: dest_port,
+                    len,
+                });
+            }
+
+            if self
+                .dag
+                .edges_directed(dest, Incoming)
+                .any(|e| e.weight().dest_port == dest_port)
+            {
+                return Err(GraphError::PortOccupied {
+                    node: dest,
+                    port: dest_port,
+                });
+            }
+
+            self.dag.add_edge(
+                src,
+                dest,
+                Edge {
+                    src_port: 0,
+                    dest_port,
+                },
+            );
+
+            Ok(())
+        }
+    }
+}
+
+/// Rebuilds the graph by reinserting its nodes and edges, since the arenas were laid out
+/// differently.
+pub(crate) fn v0_to_v1(data: &[u8]) -> Result<Vec<u8>, LoadError> {
+    let mut old: v0::ControlGraph = postcard::from_bytes(data)?;
+    let mut cg = v1::ControlGraph::new(old.sample_rate);
+
+    let edges = old
+        .dag
+        .edge_indices()
+        .map(|e| {
+            let (src, dest) = old.dag.edge_endpoints(e).unwrap();
+            (src, dest, old.dag[e])
+        })
+        .collect::<Vec<_>>();
+
+    let mut nodes = HashMap::from([(old.aout_node, cg.aout_node)]);
+    for i in old.dag.node_indices().collect::<Vec<_>>() {
+        let data = old.dag.remove_node(i).unwrap();
+        if i != old.aout_node {
+            nodes.insert(i, cg.insert(data.node));
+        }
+    }
+
+    for (src, dest, port) in edges {
+        cg.connect(nodes[&src], nodes[&dest], port)
+            .map_err(|e| LoadError::Migration {
+                from: 0,
+                message: e.to_string(),
+            })?;
+    }
+
+    cg.container_idents = old.container_idents;
+    cg.container_stack = old.container_stack;
+    cg.container_members = old
+        .container_members
+        .iter()
+        .map(|members| members.iter().map(|n| nodes[n]).collect())
+        .collect();
+    cg.container_children = old.container_children;
+
+    Ok(postcard::to_stdvec(&cg)?)
+}
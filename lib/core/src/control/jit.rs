@@ -0,0 +1,586 @@
+//! Compiles a [Program] to native code with Cranelift, for graphs made only of arithmetic,
+//! sources and oscillators. Programs that call any other node keep being interpreted.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::f64::consts;
+use std::mem::ManuallyDrop;
+
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{types, AbiParam, FuncRef, InstBuilder, MemFlags, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+use crate::node::{Opcode, Source};
+use crate::Sample;
+
+use super::{Instruction, Program};
+
+/// Evaluates `len` samples, reading and writing `registers` and `state`, into `out`.
+type Process = unsafe extern "C" fn(*mut [f64; 2], *mut [f64; 2], *mut [f64; 2], usize);
+
+/// How many samples [NativeProgram::process] evaluates per call into native code.
+const CHUNK_LEN: usize = 64;
+
+extern "C" fn sin(x: f64) -> f64 {
+    x.sin()
+}
+
+/// A [Program] compiled by [Program::jit].
+#[derive(Debug)]
+pub enum JitProgram {
+    Native(NativeProgram),
+    /// The program calls a node that can't be compiled, so it's interpreted instead.
+    Interpreted(Program),
+}
+
+/// A [Program] compiled to native code. Produces the same samples as the program would, bit for
+/// bit.
+pub struct NativeProgram {
+    /// Owns the memory that `process` points into. Boxed, since it's much larger than a [Program].
+    module: ManuallyDrop<Box<JITModule>>,
+    process: Process,
+    /// The value of every register that's carried from one sample to the next.
+    registers: Vec<[f64; 2]>,
+    /// The state of every source and oscillator, one sample each.
+    state: Vec<[f64; 2]>,
+    /// The offset into `state` of each source.
+    sources: Vec<(Source, usize)>,
+}
+
+impl Program {
+    /// Compiles the program to native code, or returns it as is if it calls a node other than
+    /// a source or an oscillator, or has feedback.
+    pub fn jit(self) -> JitProgram {
+        match NativeProgram::new(&self) {
+            Some(native) => JitProgram::Native(native),
+            None => JitProgram::Interpreted(self),
+        }
+    }
+}
+
+impl JitProgram {
+    /// Returns the next sample. See [Program::next_sample].
+    pub fn next_sample(&mut self) -> Sample {
+        match self {
+            Self::Native(native) => native.next_sample(),
+            Self::Interpreted(program) => program.next_sample(),
+        }
+    }
+
+    /// Fills `out` with the next `out.len()` samples.
+    pub fn process(&mut self, out: &mut [Sample]) {
+        match self {
+            Self::Native(native) => native.process(out),
+            Self::Interpreted(program) => program.process(out),
+        }
+    }
+
+    /// Sets the value of every node that outputs `source`. See [Program::set_source].
+    pub fn set_source(&mut self, source: Source, value: Sample) {
+        match self {
+            Self::Native(native) => native.set_source(source, value),
+            Self::Interpreted(program) => program.set_source(source, value),
+        }
+    }
+
+    /// Returns whether the program was compiled to native code.
+    pub fn is_native(&self) -> bool {
+        matches!(self, Self::Native(_))
+    }
+}
+
+impl NativeProgram {
+    /// Returns [None] if `program` can't be compiled.
+    fn new(program: &Program) -> Option<Self> {
+        if !program.feedback.is_empty() {
+            return None;
+        }
+
+        // every node that's called gets one sample of state: the phase, or the source's value
+        let mut offsets = vec![None; program.nodes.len()];
+        let mut state = vec![];
+        let mut sources = vec![];
+        for instruction in &program.instructions {
+            if let Instruction::Call { node, .. } = *instruction {
+                let (n, s) = &program.nodes[node];
+                let oscillator = matches!(
+                    n.opcode(),
+                    Some(
+                        Opcode::Sine
+                            | Opcode::Saw
+                            | Opcode::Square
+                            | Opcode::Triangle
+                            | Opcode::Pulse
+                    )
+                );
+                if !oscillator && n.get_source().is_none() {
+                    return None;
+                }
+
+                if let Some(source) = n.get_source() {
+                    sources.push((source, state.len()));
+                }
+                offsets[node] = Some(state.len());
+                state.push([s[0].l(), s[0].r()]);
+            }
+        }
+
+        let registers = program.registers.iter().map(|r| [r.l(), r.r()]).collect();
+        let (module, process) = compile(program, &offsets).ok()?;
+
+        Some(Self {
+            module: ManuallyDrop::new(Box::new(module)),
+            process,
+            registers,
+            state,
+            sources,
+        })
+    }
+
+    /// Returns the next sample.
+    pub fn next_sample(&mut self) -> Sample {
+        let mut out = [Sample::default()];
+        self.process(&mut out);
+
+        out[0]
+    }
+
+    /// Fills `out` with the next `out.len()` samples.
+    pub fn process(&mut self, out: &mut [Sample]) {
+        let mut buf = [[0.0; 2]; CHUNK_LEN];
+
+        for chunk in out.chunks_mut(CHUNK_LEN) {
+            // SAFETY: `compile` only indexes `registers` and `state` within their lengths, and
+            // only writes the first `chunk.len()` samples of `buf`
+            unsafe {
+                (self.process)(
+                    self.registers.as_mut_ptr(),
+                    self.state.as_mut_ptr(),
+                    buf.as_mut_ptr(),
+                    chunk.len(),
+                );
+            }
+
+            for (sample, &[l, r]) in chunk.iter_mut().zip(&buf) {
+                *sample = Sample::stereo(l, r);
+            }
+        }
+    }
+
+    /// Sets the value of every node that outputs `source`. See [Program::set_source].
+    pub fn set_source(&mut self, source: Source, value: Sample) {
+        for &(s, offset) in &self.sources {
+            if s == source {
+                self.state[offset] = [value.l(), value.r()];
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for NativeProgram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeProgram")
+            .field("registers", &self.registers)
+            .field("state", &self.state)
+            .field("sources", &self.sources)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for NativeProgram {
+    fn drop(&mut self) {
+        // SAFETY: `process` points into the module's memory, and is dropped along with it
+        unsafe { ManuallyDrop::take(&mut self.module).free_memory() };
+    }
+}
+
+/// Emits a function that loops over every sample, keeping each register in an SSA value.
+///
+/// Registers that no instruction writes (constants) are loaded once before the loop, and so is
+/// the value of each source, which can only change between calls. Registers that are read
+/// before they're written in a sample hold the value from the previous sample, so they're loaded
+/// from and stored to the register file.
+fn compile(
+    program: &Program,
+    offsets: &[Option<usize>],
+) -> Result<(JITModule, Process), Box<dyn Error>> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed")?;
+    let isa = cranelift_native::builder()?.finish(settings::Flags::new(flags))?;
+
+    let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+    builder.symbol("dagrid_sin", sin as *const u8);
+    let mut module = JITModule::new(builder);
+
+    let ptr = module.target_config().pointer_type();
+    let mut sig = module.make_signature();
+    sig.params.extend([AbiParam::new(ptr); 4]);
+    let func = module.declare_function("process", Linkage::Local, &sig)?;
+
+    let mut sin_sig = module.make_signature();
+    sin_sig.params.push(AbiParam::new(types::F64));
+    sin_sig.returns.push(AbiParam::new(types::F64));
+    let sin = module.declare_function("dagrid_sin", Linkage::Import, &sin_sig)?;
+
+    let mut ctx = module.make_context();
+    ctx.func.signature = sig;
+    let mut fctx = FunctionBuilderContext::new();
+    let mut b = FunctionBuilder::new(&mut ctx.func, &mut fctx);
+    let sin = module.declare_func_in_func(sin, b.func);
+
+    let written: HashSet<usize> = program
+        .instructions
+        .iter()
+        .flat_map(|instruction| match *instruction {
+            Instruction::Add { out, .. }
+            | Instruction::Mul { out, .. }
+            | Instruction::Sub { out, .. }
+            | Instruction::Inv { out, .. } => out..out + 1,
+            Instruction::Split { out, .. } => out..out + 2,
+            Instruction::Call { out, outputs, .. } => out..out + outputs,
+        })
+        .collect();
+
+    let entry = b.create_block();
+    let header = b.create_block();
+    let body = b.create_block();
+    let exit = b.create_block();
+    b.append_block_params_for_function_params(entry);
+    b.append_block_param(header, ptr);
+
+    b.switch_to_block(entry);
+    let [registers, state, out, len] = b.block_params(entry).try_into().unwrap();
+    let mut e = Emitter {
+        b,
+        sin,
+        registers,
+        state,
+        values: vec![None; program.registers.len()],
+        carried: vec![],
+    };
+
+    let mut reads: Vec<usize> = program
+        .instructions
+        .iter()
+        .flat_map(|instruction| match *instruction {
+            Instruction::Add { lhs, rhs, .. }
+            | Instruction::Mul { lhs, rhs, .. }
+            | Instruction::Sub { lhs, rhs, .. } => vec![lhs, rhs],
+            Instruction::Inv { input, .. } | Instruction::Split { input, .. } => vec![input],
+            Instruction::Call { ref inputs, .. } => program.operands[inputs.clone()].to_vec(),
+        })
+        .chain([program.out])
+        .collect();
+    reads.sort_unstable();
+    reads.dedup();
+    for &r in reads.iter().filter(|r| !written.contains(r)) {
+        e.values[r] = Some(e.load(registers, r));
+    }
+
+    let mut sources = vec![None; program.nodes.len()];
+    for instruction in &program.instructions {
+        if let Instruction::Call { node, .. } = *instruction {
+            if program.nodes[node].0.get_source().is_some() {
+                sources[node] = Some(e.load(state, offsets[node].unwrap()));
+            }
+        }
+    }
+
+    let zero = e.b.ins().iconst(ptr, 0);
+    e.b.ins().jump(header, &[zero]);
+
+    e.b.switch_to_block(header);
+    let i = e.b.block_params(header)[0];
+    let done = e.b.ins().icmp(IntCC::Equal, i, len);
+    e.b.ins().brif(done, exit, &[], body, &[]);
+
+    e.b.switch_to_block(body);
+    let sample_rate = e.splat(program.sample_rate as f64);
+
+    for instruction in &program.instructions {
+        match *instruction {
+            Instruction::Add { lhs, rhs, out } => {
+                let (lhs, rhs) = (e.read(lhs), e.read(rhs));
+                let val = e.b.ins().fadd(lhs, rhs);
+                e.write(out, val);
+            }
+            Instruction::Mul { lhs, rhs, out } => {
+                let (lhs, rhs) = (e.read(lhs), e.read(rhs));
+                let val = e.b.ins().fmul(lhs, rhs);
+                e.write(out, val);
+            }
+            Instruction::Sub { lhs, rhs, out } => {
+                let (lhs, rhs) = (e.read(lhs), e.read(rhs));
+                let val = e.b.ins().fsub(lhs, rhs);
+                e.write(out, val);
+            }
+            Instruction::Inv { input, out } => {
+                let one = e.splat(1.0);
+                let input = e.read(input);
+                let val = e.b.ins().fdiv(one, input);
+                e.write(out, val);
+            }
+            Instruction::Split { input, out } => {
+                let val = e.read(input);
+                for lane in 0..2 {
+                    let channel = e.b.ins().extractlane(val, lane);
+                    let mono = e.b.ins().splat(types::F64X2, channel);
+                    e.write(out + lane as usize, mono);
+                }
+            }
+            Instruction::Call {
+                node,
+                ref inputs,
+                out,
+                ..
+            } => {
+                if let Some(val) = sources[node] {
+                    e.write(out, val);
+                    continue;
+                }
+
+                let inputs: Vec<Value> = program.operands[inputs.clone()]
+                    .iter()
+                    .map(|&r| e.read(r))
+                    .collect();
+                let (t, dt) = e.accumulate(offsets[node].unwrap(), inputs[0], sample_rate);
+
+                let val = match program.nodes[node].0.opcode() {
+                    Some(Opcode::Sine) => {
+                        let tau = e.splat(consts::TAU);
+                        let x = e.b.ins().fmul(t, tau);
+                        e.per_lane(x, |e, x| {
+                            let call = e.b.ins().call(e.sin, &[x]);
+                            e.b.inst_results(call)[0]
+                        })
+                    }
+                    Some(Opcode::Saw) => e.saw(t, dt),
+                    Some(Opcode::Square) => {
+                        let width = e.splat(0.5);
+                        e.pulse(t, dt, width)
+                    }
+                    Some(Opcode::Triangle) => e.triangle(t, dt),
+                    Some(Opcode::Pulse) => {
+                        let width = e.clamp(inputs[1], 0.0, 1.0);
+                        e.pulse(t, dt, width)
+                    }
+                    _ => unreachable!(),
+                };
+                e.write(out, val);
+            }
+        }
+    }
+
+    let val = e.read(program.out);
+    let sixteen = e.b.ins().iconst(ptr, 16);
+    let offset = e.b.ins().imul(i, sixteen);
+    let addr = e.b.ins().iadd(out, offset);
+    e.b.ins().store(MemFlags::trusted(), val, addr, 0);
+
+    for r in std::mem::take(&mut e.carried) {
+        let val = e.values[r].unwrap();
+        e.store(registers, r, val);
+    }
+
+    let next = e.b.ins().iadd_imm(i, 1);
+    e.b.ins().jump(header, &[next]);
+
+    e.b.switch_to_block(exit);
+    e.b.ins().return_(&[]);
+    e.b.seal_all_blocks();
+    e.b.finalize();
+
+    module.define_function(func, &mut ctx)?;
+    module.clear_context(&mut ctx);
+    module.finalize_definitions()?;
+
+    // SAFETY: the function was declared with the signature of `Process`
+    let process =
+        unsafe { std::mem::transmute::<*const u8, Process>(module.get_finalized_function(func)) };
+
+    Ok((module, process))
+}
+
+/// Builds the body of the function, one F64X2 vector per sample.
+struct Emitter<'a> {
+    b: FunctionBuilder<'a>,
+    sin: FuncRef,
+    registers: Value,
+    state: Value,
+    /// The value of each register in the current sample, once it's been read or written.
+    values: Vec<Option<Value>>,
+    /// The registers that are read before they're written.
+    carried: Vec<usize>,
+}
+
+impl Emitter<'_> {
+    fn load(&mut self, base: Value, index: usize) -> Value {
+        let offset = (index * 16) as i32;
+        self.b
+            .ins()
+            .load(types::F64X2, MemFlags::trusted(), base, offset)
+    }
+
+    fn store(&mut self, base: Value, index: usize, val: Value) {
+        let offset = (index * 16) as i32;
+        self.b.ins().store(MemFlags::trusted(), val, base, offset);
+    }
+
+    fn splat(&mut self, x: f64) -> Value {
+        let x = self.b.ins().f64const(x);
+        self.b.ins().splat(types::F64X2, x)
+    }
+
+    fn read(&mut self, r: usize) -> Value {
+        match self.values[r] {
+            Some(val) => val,
+            None => {
+                let val = self.load(self.registers, r);
+                self.values[r] = Some(val);
+                self.carried.push(r);
+                val
+            }
+        }
+    }
+
+    fn write(&mut self, r: usize, val: Value) {
+        self.values[r] = Some(val);
+    }
+
+    /// Picks `a` in each lane where `cond` holds, otherwise `b`.
+    fn select(&mut self, cond: FloatCC, x: Value, y: Value, a: Value, b: Value) -> Value {
+        let mask = self.b.ins().fcmp(cond, x, y);
+        let mask = self.b.ins().bitcast(types::F64X2, MemFlags::new(), mask);
+        self.b.ins().bitselect(mask, a, b)
+    }
+
+    /// Applies `f` to both lanes of `x`.
+    fn per_lane(&mut self, x: Value, f: impl Fn(&mut Self, Value) -> Value) -> Value {
+        let l = self.b.ins().extractlane(x, 0);
+        let l = f(self, l);
+        let r = self.b.ins().extractlane(x, 1);
+        let r = f(self, r);
+        let val = self.b.ins().splat(types::F64X2, l);
+        self.b.ins().insertlane(val, r, 1)
+    }
+
+    /// Like `Sample::clamp`, which returns `min` for NaN.
+    fn clamp(&mut self, x: Value, min: f64, max: f64) -> Value {
+        let min = self.splat(min);
+        let max = self.splat(max);
+        let x = self.select(FloatCC::GreaterThan, x, min, x, min);
+        self.select(FloatCC::LessThan, x, max, x, max)
+    }
+
+    /// See `accumulate` in [crate::node::osc].
+    fn accumulate(&mut self, offset: usize, freq: Value, sample_rate: Value) -> (Value, Value) {
+        let phase = self.load(self.state, offset);
+        let dt = self.b.ins().fdiv(freq, sample_rate);
+        let next = self.b.ins().fadd(phase, dt);
+        let floor = self.b.ins().floor(next);
+        let next = self.b.ins().fsub(next, floor);
+        self.store(self.state, offset, next);
+
+        (phase, self.b.ins().fabs(dt))
+    }
+
+    fn poly_blep(&mut self, t: Value, dt: Value) -> Value {
+        let one = self.splat(1.0);
+        let two = self.splat(2.0);
+        let zero = self.splat(0.0);
+
+        let u = self.b.ins().fdiv(t, dt);
+        let u2 = self.b.ins().fmul(two, u);
+        let uu = self.b.ins().fmul(u, u);
+        let low = self.b.ins().fsub(u2, uu);
+        let low = self.b.ins().fsub(low, one);
+
+        let u = self.b.ins().fsub(t, one);
+        let u = self.b.ins().fdiv(u, dt);
+        let uu = self.b.ins().fmul(u, u);
+        let u2 = self.b.ins().fmul(two, u);
+        let high = self.b.ins().fadd(uu, u2);
+        let high = self.b.ins().fadd(high, one);
+
+        let edge = self.b.ins().fsub(one, dt);
+        let val = self.select(FloatCC::GreaterThan, t, edge, high, zero);
+        self.select(FloatCC::LessThan, t, dt, low, val)
+    }
+
+    fn poly_blamp(&mut self, t: Value, dt: Value) -> Value {
+        let one = self.splat(1.0);
+        let three = self.splat(3.0);
+        let zero = self.splat(0.0);
+
+        let u = self.b.ins().fdiv(t, dt);
+        let u = self.b.ins().fsub(u, one);
+        let neg = self.b.ins().fneg(u);
+        let low = self.b.ins().fmul(neg, u);
+        let low = self.b.ins().fmul(low, u);
+        let low = self.b.ins().fdiv(low, three);
+
+        let u = self.b.ins().fsub(t, one);
+        let u = self.b.ins().fdiv(u, dt);
+        let u = self.b.ins().fadd(u, one);
+        let high = self.b.ins().fmul(u, u);
+        let high = self.b.ins().fmul(high, u);
+        let high = self.b.ins().fdiv(high, three);
+
+        let edge = self.b.ins().fsub(one, dt);
+        let val = self.select(FloatCC::GreaterThan, t, edge, high, zero);
+        self.select(FloatCC::LessThan, t, dt, low, val)
+    }
+
+    fn saw(&mut self, t: Value, dt: Value) -> Value {
+        let one = self.splat(1.0);
+        let two = self.splat(2.0);
+
+        let naive = self.b.ins().fmul(two, t);
+        let naive = self.b.ins().fsub(naive, one);
+        let blep = self.poly_blep(t, dt);
+        self.b.ins().fsub(naive, blep)
+    }
+
+    fn pulse(&mut self, t: Value, dt: Value, width: Value) -> Value {
+        let one = self.splat(1.0);
+        let neg_one = self.splat(-1.0);
+
+        let naive = self.select(FloatCC::LessThan, t, width, one, neg_one);
+        let falling = self.b.ins().fsub(t, width);
+        let floor = self.b.ins().floor(falling);
+        let falling = self.b.ins().fsub(falling, floor);
+
+        let rise = self.poly_blep(t, dt);
+        let fall = self.poly_blep(falling, dt);
+        let val = self.b.ins().fadd(naive, rise);
+        self.b.ins().fsub(val, fall)
+    }
+
+    fn triangle(&mut self, t: Value, dt: Value) -> Value {
+        let half = self.splat(0.5);
+        let one = self.splat(1.0);
+        let two = self.splat(2.0);
+        let eight = self.splat(8.0);
+
+        let naive = self.b.ins().fmul(two, t);
+        let naive = self.b.ins().fsub(naive, one);
+        let naive = self.b.ins().fabs(naive);
+        let naive = self.b.ins().fmul(two, naive);
+        let naive = self.b.ins().fsub(naive, one);
+
+        let rising = self.b.ins().fadd(t, half);
+        let floor = self.b.ins().floor(rising);
+        let rising = self.b.ins().fsub(rising, floor);
+
+        let slope = self.b.ins().fmul(eight, dt);
+        let corner = self.poly_blamp(t, dt);
+        let corner = self.b.ins().fmul(slope, corner);
+        let val = self.b.ins().fsub(naive, corner);
+        let corner = self.poly_blamp(rising, dt);
+        let corner = self.b.ins().fmul(slope, corner);
+        self.b.ins().fadd(val, corner)
+    }
+}
@@ -0,0 +1,318 @@
+//! A control graph lowered into a flat list of instructions over a register file, which is
+//! evaluated without touching the graph structure.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use petgraph::graph::NodeIndex;
+
+use crate::error::GraphError;
+use crate::node::{Feedback, Node, Opcode, Source};
+use crate::Sample;
+
+use super::ControlGraph;
+
+/// A single step of a [Program]. Operands and results are indices into the register file, which
+/// holds the value of every output port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    Add {
+        lhs: usize,
+        rhs: usize,
+        out: usize,
+    },
+    Mul {
+        lhs: usize,
+        rhs: usize,
+        out: usize,
+    },
+    /// The sum of `lhs` and the negation of `rhs`, as built by the [Sub](crate::container::Sub)
+    /// container. Only emitted when the graph is optimized.
+    Sub {
+        lhs: usize,
+        rhs: usize,
+        out: usize,
+    },
+    Inv {
+        input: usize,
+        out: usize,
+    },
+    /// Writes the left channel to `out` and the right channel to `out + 1`.
+    Split {
+        input: usize,
+        out: usize,
+    },
+    /// Calls [Node::process_multi] on node `node` of the program, with the registers listed in
+    /// `operands[inputs]`, writing its outputs into the registers from `out` onwards.
+    Call {
+        node: usize,
+        inputs: Range<usize>,
+        out: usize,
+        outputs: usize,
+    },
+}
+
+/// A control graph compiled by [ControlGraph::compile]. Produces the same samples as the graph
+/// would, bit for bit.
+#[derive(Debug)]
+pub struct Program {
+    pub(super) phase: u64,
+    pub(super) sample_rate: u32,
+    pub(super) instructions: Vec<Instruction>,
+    /// The value of every output port, along with the values of constants.
+    pub(super) registers: Vec<Sample>,
+    /// The input registers of each [Instruction::Call].
+    pub(super) operands: Vec<usize>,
+    /// Holds the inputs of an [Instruction::Call], which are passed to the node as a slice.
+    pub(super) args: Vec<Sample>,
+    /// The nodes called by [Instruction::Call], along with their state.
+    pub(super) nodes: Vec<(Box<dyn Node>, Vec<Sample>)>,
+    /// The register of each feedback source, along with the node of its tap.
+    pub(super) feedback: Vec<(usize, usize)>,
+    /// The register routed to `aout`.
+    pub(super) out: usize,
+}
+
+impl ControlGraph {
+    /// Lowers the cached topological order of the graph into a [Program].
+    ///
+    /// If the cache has to be built first, as it does after any edit, that evaluates one sample
+    /// and the graph is then reset to phase 0. Otherwise the program carries on from the graph's
+    /// phase and state.
+    ///
+    /// Returns [GraphError::AoutUnconnected] if nothing is connected to `aout`.
+    pub fn compile(mut self) -> Result<Program, GraphError> {
+        if self.cache_invalid {
+            self.try_next_sample()?;
+            self.reset_phase();
+        }
+
+        let mut program = Program {
+            phase: self.phase,
+            sample_rate: self.sample_rate,
+            instructions: Vec::with_capacity(self.cache.len()),
+            registers: std::mem::take(&mut self.node_output_val_arena),
+            operands: vec![],
+            args: vec![],
+            nodes: vec![],
+            feedback: vec![],
+            out: self.aout_src,
+        };
+
+        // the index of each node that's been moved into the program
+        let mut nodes = HashMap::new();
+        let mut take = |dag: &mut ControlGraph, program: &mut Program, node: NodeIndex| {
+            *nodes.entry(node).or_insert_with(|| {
+                let data = dag.dag.remove_node(node).unwrap();
+                program.nodes.push((data.node, data.state));
+                program.nodes.len() - 1
+            })
+        };
+
+        for i in 0..self.cache.len() {
+            let (node, input_arena_ptr, out) = self.cache[i];
+            let data = &self.dag[node];
+            let input_len = data.node.get_input_labels().len();
+            let inputs = &self.node_input_arena[input_arena_ptr..input_arena_ptr + input_len];
+
+            let instruction = match data.node.opcode() {
+                Some(Opcode::Add) => Instruction::Add {
+                    lhs: inputs[0],
+                    rhs: inputs[1],
+                    out,
+                },
+                Some(Opcode::Mul) => Instruction::Mul {
+                    lhs: inputs[0],
+                    rhs: inputs[1],
+                    out,
+                },
+                Some(Opcode::Inv) => Instruction::Inv {
+                    input: inputs[0],
+                    out,
+                },
+                Some(Opcode::Split) => Instruction::Split {
+                    input: inputs[0],
+                    out,
+                },
+                _ => {
+                    let start = program.operands.len();
+                    let outputs = data.node.get_output_labels().len();
+                    program.operands.extend_from_slice(inputs);
+                    program
+                        .args
+                        .resize(program.args.len().max(input_len), Sample::default());
+
+                    Instruction::Call {
+                        node: take(&mut self, &mut program, node),
+                        inputs: start..program.operands.len(),
+                        out,
+                        outputs,
+                    }
+                }
+            };
+
+            program.instructions.push(instruction);
+        }
+
+        // taps that don't lead to `aout` aren't called, but are still pushed into
+        for &(slot, tap) in &self.feedback_slots.clone() {
+            let tap = take(&mut self, &mut program, tap);
+            program.feedback.push((slot, tap));
+        }
+
+        if self.optimize {
+            program.fuse_sub();
+        }
+
+        Ok(program)
+    }
+}
+
+impl Program {
+    /// Replaces each `Add` of a `Mul` by a constant -1 with a `Sub`, when nothing else reads the
+    /// product. `a + b * -1` and `a - b` are equal in IEEE arithmetic, so the output doesn't
+    /// change.
+    fn fuse_sub(&mut self) {
+        let mut written = vec![false; self.registers.len()];
+        let mut readers = vec![0; self.registers.len()];
+        for instruction in &self.instructions {
+            match *instruction {
+                Instruction::Add { lhs, rhs, out }
+                | Instruction::Mul { lhs, rhs, out }
+                | Instruction::Sub { lhs, rhs, out } => {
+                    readers[lhs] += 1;
+                    readers[rhs] += 1;
+                    written[out] = true;
+                }
+                Instruction::Inv { input, out } => {
+                    readers[input] += 1;
+                    written[out] = true;
+                }
+                Instruction::Split { input, out } => {
+                    readers[input] += 1;
+                    written[out..out + 2].fill(true);
+                }
+                Instruction::Call { out, outputs, .. } => written[out..out + outputs].fill(true),
+            }
+        }
+        for &operand in &self.operands {
+            readers[operand] += 1;
+        }
+        for &(slot, _) in &self.feedback {
+            readers[slot] += 1;
+        }
+        readers[self.out] += 1;
+
+        let negative_one = Sample::mono(-1.0);
+        let is_negative_one = |r: usize| !written[r] && self.registers[r] == negative_one;
+
+        // the operand of each `Mul` by -1 whose product is only read once, by its register
+        let mut negations = HashMap::new();
+        for (i, instruction) in self.instructions.iter().enumerate() {
+            if let Instruction::Mul { lhs, rhs, out } = *instruction {
+                if readers[out] != 1 {
+                    continue;
+                }
+
+                if is_negative_one(lhs) {
+                    negations.insert(out, (i, rhs));
+                } else if is_negative_one(rhs) {
+                    negations.insert(out, (i, lhs));
+                }
+            }
+        }
+
+        let mut removed = vec![false; self.instructions.len()];
+        for instruction in &mut self.instructions {
+            if let Instruction::Add { lhs, rhs, out } = *instruction {
+                let (sum, (mul, negated)) = match (negations.get(&rhs), negations.get(&lhs)) {
+                    (Some(&n), _) => (lhs, n),
+                    (None, Some(&n)) => (rhs, n),
+                    (None, None) => continue,
+                };
+
+                *instruction = Instruction::Sub {
+                    lhs: sum,
+                    rhs: negated,
+                    out,
+                };
+                removed[mul] = true;
+            }
+        }
+
+        let mut i = 0;
+        self.instructions.retain(|_| {
+            i += 1;
+            !removed[i - 1]
+        });
+    }
+
+    /// Evaluates every instruction.
+    ///
+    /// Returns the next sample.
+    pub fn next_sample(&mut self) -> Sample {
+        let r = &mut self.registers;
+
+        for instruction in &self.instructions {
+            match *instruction {
+                Instruction::Add { lhs, rhs, out } => r[out] = r[lhs] + r[rhs],
+                Instruction::Mul { lhs, rhs, out } => r[out] = r[lhs] * r[rhs],
+                Instruction::Sub { lhs, rhs, out } => r[out] = r[lhs] - r[rhs],
+                Instruction::Inv { input, out } => r[out] = r[input].recip(),
+                Instruction::Split { input, out } => {
+                    let val = r[input];
+                    r[out] = Sample::mono(val.l());
+                    r[out + 1] = Sample::mono(val.r());
+                }
+                Instruction::Call {
+                    node,
+                    ref inputs,
+                    out,
+                    outputs,
+                } => {
+                    let args = &mut self.args[..inputs.len()];
+                    for (arg, &operand) in args.iter_mut().zip(&self.operands[inputs.clone()]) {
+                        *arg = r[operand];
+                    }
+
+                    let (node, state) = &mut self.nodes[node];
+                    node.process_multi(
+                        args,
+                        state,
+                        &mut r[out..out + outputs],
+                        self.phase,
+                        self.sample_rate,
+                    );
+                }
+            }
+        }
+
+        for &(slot, tap) in &self.feedback {
+            Feedback::push(&mut self.nodes[tap].1, r[slot]);
+        }
+
+        self.phase += 1;
+
+        r[self.out]
+    }
+
+    /// Fills `out` with the next `out.len()` samples.
+    pub fn process(&mut self, out: &mut [Sample]) {
+        for sample in out {
+            *sample = self.next_sample();
+        }
+    }
+
+    /// Sets the value of every node that outputs `source`. See [ControlGraph::set_source].
+    pub fn set_source(&mut self, source: Source, value: Sample) {
+        for (node, state) in &mut self.nodes {
+            if node.get_source() == Some(source) {
+                state[0] = value;
+            }
+        }
+    }
+
+    pub fn get_instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+}
@@ -0,0 +1,137 @@
+use std::fmt::Display;
+
+use petgraph::graph::NodeIndex;
+
+/// Errors returned when wiring or evaluating a [ControlGraph](crate::control::ControlGraph).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphError {
+    /// Adding the edge `src -> dest` would create a cycle.
+    WouldCycle { src: NodeIndex, dest: NodeIndex },
+    /// The node doesn't exist in the graph.
+    NoSuchNode(NodeIndex),
+    /// The node has no port called `label`. `valid` lists the labels it does have.
+    NoSuchPort {
+        node: NodeIndex,
+        label: String,
+        valid: Vec<String>,
+    },
+    /// The container has no port called `label`. `valid` lists the labels it does have.
+    NoSuchContainerPort { label: String, valid: Vec<String> },
+    /// `port` is past the `len` ports of the node.
+    PortOutOfRange {
+        node: NodeIndex,
+        port: usize,
+        len: usize,
+    },
+    /// Output `port` is past the `len` outputs of the node.
+    OutputOutOfRange {
+        node: NodeIndex,
+        port: usize,
+        len: usize,
+    },
+    /// `port` of the node already has an edge connected to it.
+    PortOccupied { node: NodeIndex, port: usize },
+    /// Feedback connections need a delay of at least one sample.
+    FeedbackWithoutDelay { src: NodeIndex, dest: NodeIndex },
+    /// Nothing is connected to `aout`, so there's nothing to evaluate.
+    AoutUnconnected,
+}
+
+impl Display for GraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WouldCycle { src, dest } => {
+                write!(f, "adding edge {src:?} -> {dest:?} would cause a cycle")
+            }
+            Self::NoSuchNode(node) => write!(f, "node {node:?} doesn't exist"),
+            Self::NoSuchPort { node, label, valid } => write!(
+                f,
+                "node {node:?} has no port named \"{label}\" (expected one of: {})",
+                valid.join(", ")
+            ),
+            Self::NoSuchContainerPort { label, valid } => write!(
+                f,
+                "container has no port named \"{label}\" (expected one of: {})",
+                valid.join(", ")
+            ),
+            Self::PortOutOfRange { node, port, len } => write!(
+                f,
+                "port {port} is out of range for node {node:?}, which has {len} ports"
+            ),
+            Self::OutputOutOfRange { node, port, len } => write!(
+                f,
+                "output {port} is out of range for node {node:?}, which has {len} outputs"
+            ),
+            Self::PortOccupied { node, port } => {
+                write!(f, "port {port} of node {node:?} is already connected")
+            }
+            Self::FeedbackWithoutDelay { src, dest } => write!(
+                f,
+                "feedback from {src:?} to {dest:?} needs a delay of at least one sample"
+            ),
+            Self::AoutUnconnected => write!(f, "nothing is connected to aout"),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+/// Errors returned when creating a [MultiStageEnvelope](crate::node::MultiStageEnvelope).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvelopeError {
+    /// Envelopes need at least one stage.
+    NoStages,
+    /// The sustain stage is past the last of `stages` stages, which would leave the envelope
+    /// stuck when the gate closes.
+    SustainPastEnd { sustain: usize, stages: usize },
+}
+
+impl Display for EnvelopeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoStages => write!(f, "envelopes need at least one stage"),
+            Self::SustainPastEnd { sustain, stages } => write!(
+                f,
+                "sustain stage {sustain} is past the last of {stages} stages (stages go from 0 to {})",
+                stages - 1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+/// Errors returned when loading a saved [ControlGraph](crate::control::ControlGraph).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    /// The data was saved in a newer format version than this build supports.
+    NewerVersion(u32),
+    /// The data couldn't be migrated from format version `from`.
+    Migration { from: u32, message: String },
+    /// The data couldn't be decoded.
+    Decode(postcard::Error),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NewerVersion(version) => write!(
+                f,
+                "the data was saved in format version {version}, but only versions up to {} are supported",
+                crate::control::FORMAT_VERSION
+            ),
+            Self::Migration { from, message } => {
+                write!(f, "couldn't migrate the data from format version {from}: {message}")
+            }
+            Self::Decode(e) => write!(f, "couldn't decode the data: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<postcard::Error> for LoadError {
+    fn from(e: postcard::Error) -> Self {
+        Self::Decode(e)
+    }
+}
@@ -0,0 +1,106 @@
+//! Hands values, such as edited control graphs, from one thread to a real-time thread that must
+//! never block, allocate or free memory.
+
+use std::marker::PhantomData;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/// A wait-free slot that one or more threads [publish](Handoff::publish) values into, and a
+/// single real-time thread [receives](Handoff::receive) them from.
+///
+/// Received values are handed back as retired, and dropped by the next call to
+/// [publish](Handoff::publish) or [collect](Handoff::collect) rather than on the receiving
+/// thread.
+pub struct Handoff<T> {
+    /// The latest published value that hasn't been received yet.
+    pending: AtomicPtr<T>,
+    /// The last received value, after the receiver has swapped its contents out.
+    retired: AtomicPtr<T>,
+    _owns: PhantomData<Box<T>>,
+}
+
+impl<T> Default for Handoff<T> {
+    fn default() -> Self {
+        Self {
+            pending: AtomicPtr::new(null_mut()),
+            retired: AtomicPtr::new(null_mut()),
+            _owns: PhantomData,
+        }
+    }
+}
+
+impl<T> Handoff<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Publishes `value`, replacing the pending value if it hasn't been received yet.
+    pub fn publish(&self, value: T) {
+        self.collect();
+
+        let old = self
+            .pending
+            .swap(Box::into_raw(Box::new(value)), Ordering::AcqRel);
+
+        // SAFETY: non-null pointers in `pending` come from `Box::into_raw`, and swapping them out
+        // transfers their ownership
+        if !old.is_null() {
+            drop(unsafe { Box::from_raw(old) });
+        }
+    }
+
+    /// Drops the value retired by the last [receive](Handoff::receive).
+    pub fn collect(&self) {
+        let old = self.retired.swap(null_mut(), Ordering::AcqRel);
+
+        // SAFETY: as in `publish`
+        if !old.is_null() {
+            drop(unsafe { Box::from_raw(old) });
+        }
+    }
+
+    /// Calls `f` with the latest published value, if there is one, then retires it. `f` is
+    /// expected to swap the value's contents with the ones that it replaces, so that they're
+    /// dropped by the publishing thread.
+    ///
+    /// Returns whether a value was received. Values aren't received until the last retired value
+    /// has been collected, so that it doesn't have to be dropped here.
+    pub fn receive(&self, f: impl FnOnce(&mut T)) -> bool {
+        // only the receiver stores into `retired`, so it stays empty once it's seen empty
+        if !self.retired.load(Ordering::Acquire).is_null() {
+            return false;
+        }
+
+        let new = self.pending.swap(null_mut(), Ordering::AcqRel);
+        if new.is_null() {
+            return false;
+        }
+
+        // SAFETY: as in `publish`, and the box is handed back before returning
+        let mut value = unsafe { Box::from_raw(new) };
+        f(&mut value);
+        self.retired.store(Box::into_raw(value), Ordering::Release);
+
+        true
+    }
+}
+
+// SAFETY: a `Handoff` owns its values, and dropping it drops them, so it can be sent wherever they
+// can
+unsafe impl<T: Send> Send for Handoff<T> {}
+
+// SAFETY: values only ever move between threads through atomic swaps, and `receive` lends one to a
+// single thread at a time, so no `&T` is ever shared and `T` needn't be `Sync`
+unsafe impl<T: Send> Sync for Handoff<T> {}
+
+impl<T> Drop for Handoff<T> {
+    fn drop(&mut self) {
+        self.collect();
+
+        let pending = *self.pending.get_mut();
+        // SAFETY: as in `publish`
+        if !pending.is_null() {
+            drop(unsafe { Box::from_raw(pending) });
+        }
+    }
+}
@@ -0,0 +1,3 @@
+//! Builds control graphs from code written for other environments.
+
+pub mod glicol;
@@ -0,0 +1,292 @@
+//! Imports the subset of [glicol](https://glicol.org) that maps onto dagrid nodes.
+//!
+//! ```text
+//! ~s1: sin 440
+//! ~s2: sin 220
+//! o: ~s2 >> mul -1 >> add ~s1 >> mul 0.5
+//! ```
+//!
+//! Chains begin with `sin` or `constsig`, or a reference to another chain
+//! such as `~s1`, then continue with `mul` and `add`. Parameters are either numbers or
+//! references. Chains named without a leading `~` are summed into `aout`.
+
+use std::collections::HashMap;
+
+use petgraph::graph::NodeIndex;
+
+use crate::control::ControlGraph;
+use crate::node::*;
+use crate::patch::PatchError;
+
+/// Parses glicol code into a control graph with its `sample_rate` set.
+pub fn parse(sample_rate: u32, src: &str) -> Result<ControlGraph, PatchError> {
+    let mut chains = vec![];
+    for (i, line) in src.lines().enumerate() {
+        if let Some(chain) = Chain::parse(i + 1, line)? {
+            if chains.iter().any(|c: &Chain| c.name == chain.name) {
+                return Err(chain.err(
+                    chain.name_column,
+                    format!("`{}` is already defined", chain.name),
+                ));
+            }
+
+            chains.push(chain);
+        }
+    }
+
+    let mut importer = Importer {
+        cg: ControlGraph::new(sample_rate),
+        chains: &chains,
+        built: HashMap::new(),
+        building: vec![],
+    };
+
+    let mut outputs = vec![];
+    for (i, chain) in chains.iter().enumerate() {
+        if !chain.name.starts_with('~') {
+            outputs.push(importer.chain(i)?);
+        }
+    }
+
+    let Some((&first, rest)) = outputs.split_first() else {
+        return Err(PatchError {
+            line: 1,
+            column: 1,
+            message: "there's no output chain, such as `o: sin 440`".into(),
+        });
+    };
+
+    let mut cg = importer.cg;
+    let out = rest
+        .iter()
+        .fold(first, |sum, &o| cg.connect_many_new(&[sum, o], Add));
+    cg.connect_ex_aout(out);
+
+    Ok(cg)
+}
+
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+/// A call such as `mul 0.5`, or a reference such as `~s1`.
+struct Call<'a> {
+    name: Token<'a>,
+    args: Vec<Token<'a>>,
+}
+
+/// A line such as `~s1: sin 440 >> mul 0.5`.
+struct Chain<'a> {
+    line: usize,
+    name: &'a str,
+    name_column: usize,
+    calls: Vec<Call<'a>>,
+}
+
+impl<'a> Chain<'a> {
+    /// Returns `None` for blank lines and comments.
+    fn parse(line: usize, src: &'a str) -> Result<Option<Self>, PatchError> {
+        let src = src.split("//").next().unwrap();
+        if src.trim().is_empty() {
+            return Ok(None);
+        }
+
+        let name_column = column(src, src.trim_start());
+        let err = |message: String| PatchError {
+            line,
+            column: name_column,
+            message,
+        };
+
+        let Some((name, body)) = src.split_once(':') else {
+            return Err(err("expected a chain, such as `o: sin 440`".into()));
+        };
+
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(err(format!("expected a chain name, found `{name}`")));
+        }
+
+        let mut chain = Chain {
+            line,
+            name,
+            name_column,
+            calls: vec![],
+        };
+
+        let mut call: Option<Call> = None;
+        for text in body.split_whitespace() {
+            let token = Token {
+                text,
+                column: column(src, text),
+            };
+
+            if text == ">>" {
+                match call.take() {
+                    Some(c) => chain.calls.push(c),
+                    None => return Err(chain.err(token.column, "expected a node before `>>`")),
+                }
+            } else if let Some(c) = &mut call {
+                c.args.push(token);
+            } else {
+                call = Some(Call {
+                    name: token,
+                    args: vec![],
+                });
+            }
+        }
+
+        match call {
+            Some(c) => chain.calls.push(c),
+            None => return Err(chain.err(src.trim_end().chars().count() + 1, "expected a node")),
+        }
+
+        Ok(Some(chain))
+    }
+
+    fn err(&self, column: usize, message: impl Into<String>) -> PatchError {
+        PatchError {
+            line: self.line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+struct Importer<'a> {
+    cg: ControlGraph,
+    chains: &'a [Chain<'a>],
+    /// The node at the end of each chain that has already been built.
+    built: HashMap<&'a str, NodeIndex>,
+    /// The chains being built, to catch references that loop back to themselves.
+    building: Vec<&'a str>,
+}
+
+impl<'a> Importer<'a> {
+    /// Builds chain `i`, and returns the node at the end of it.
+    fn chain(&mut self, i: usize) -> Result<NodeIndex, PatchError> {
+        let chain = &self.chains[i];
+        if let Some(&node) = self.built.get(chain.name) {
+            return Ok(node);
+        }
+
+        self.building.push(chain.name);
+
+        let (first, rest) = chain.calls.split_first().unwrap();
+        let mut node = self.source(chain, first)?;
+
+        for call in rest {
+            let dest = match call.name.text {
+                "mul" => self.cg.insert(Mul),
+                "add" => self.cg.insert(Add),
+                "sin" | "constsig" => {
+                    return Err(chain.err(
+                        call.name.column,
+                        format!("`{}` can only begin a chain", call.name.text),
+                    ))
+                }
+                _ => return Err(unsupported(chain, call)),
+            };
+
+            let param = self.param(chain, call)?;
+            self.cg.connect_many_ex(&[node, param], dest);
+            node = dest;
+        }
+
+        self.building.pop();
+        self.built.insert(chain.name, node);
+
+        Ok(node)
+    }
+
+    /// Builds the first call of a chain.
+    fn source(&mut self, chain: &Chain<'a>, call: &Call<'a>) -> Result<NodeIndex, PatchError> {
+        let node = match call.name.text {
+            "sin" => self.cg.insert(Sine),
+            "constsig" => return self.param(chain, call),
+            "mul" | "add" => {
+                return Err(chain.err(
+                    call.name.column,
+                    format!("`{}` needs an input before it", call.name.text),
+                ))
+            }
+            r if r.starts_with('~') => {
+                if let Some(arg) = call.args.first() {
+                    return Err(chain.err(arg.column, format!("unexpected `{}`", arg.text)));
+                }
+
+                return self.reference(chain, &call.name);
+            }
+            _ => return Err(unsupported(chain, call)),
+        };
+
+        let param = self.param(chain, call)?;
+        self.cg.connect(param, node, 0);
+
+        Ok(node)
+    }
+
+    /// Builds the single parameter of `call`, which is a number or a reference.
+    fn param(&mut self, chain: &Chain<'a>, call: &Call<'a>) -> Result<NodeIndex, PatchError> {
+        let Some(arg) = call.args.first() else {
+            return Err(chain.err(
+                call.name.column + call.name.text.chars().count(),
+                format!("`{}` needs a parameter", call.name.text),
+            ));
+        };
+
+        if let Some(extra) = call.args.get(1) {
+            return Err(chain.err(extra.column, format!("unexpected `{}`", extra.text)));
+        }
+
+        if arg.text.starts_with('~') {
+            return self.reference(chain, arg);
+        }
+
+        arg.text.parse().map(|n| self.cg.insert(c(n))).map_err(|_| {
+            chain.err(
+                arg.column,
+                format!(
+                    "unsupported parameter `{}`; only numbers and references are supported",
+                    arg.text
+                ),
+            )
+        })
+    }
+
+    fn reference(&mut self, chain: &Chain<'a>, name: &Token<'a>) -> Result<NodeIndex, PatchError> {
+        if self.building.contains(&name.text) {
+            return Err(chain.err(
+                name.column,
+                format!("`{}` refers back to itself", name.text),
+            ));
+        }
+
+        let i = self
+            .chains
+            .iter()
+            .position(|c| c.name == name.text)
+            .ok_or_else(|| chain.err(name.column, format!("`{}` isn't defined", name.text)))?;
+
+        self.chain(i)
+    }
+}
+
+/// Returns the 1-based column of `sub` within `src`, which it must be a subslice of.
+fn column(src: &str, sub: &str) -> usize {
+    src[..sub.as_ptr() as usize - src.as_ptr() as usize]
+        .chars()
+        .count()
+        + 1
+}
+
+fn unsupported(chain: &Chain, call: &Call) -> PatchError {
+    chain.err(
+        call.name.column,
+        format!(
+            "unsupported glicol node `{}` (expected one of: sin, constsig, mul, add)",
+            call.name.text
+        ),
+    )
+}
@@ -3,7 +3,12 @@
 
 pub mod container;
 pub mod control;
+pub mod error;
+pub mod handoff;
+pub mod import;
 pub mod node;
+pub mod patch;
+pub mod pool;
 pub mod presets;
 pub mod util;
 pub mod vis;
@@ -1,13 +1,146 @@
-use std::{borrow::Cow, f64::consts, fmt::Debug};
+use std::{borrow::Cow, fmt::Debug};
 
 use crate::Sample;
 use serde::{Deserialize, Serialize};
 
+mod delay;
+mod envelope;
+mod filter;
+mod lfo;
+mod osc;
+mod source;
+pub use delay::*;
+pub use envelope::*;
+pub use filter::*;
+pub use lfo::*;
+pub use osc::*;
+pub use source::*;
+
+/// Replaces each channel of `x` that isn't a finite number, such as that of an unconnected input,
+/// with `default`.
+fn or_default(x: Sample, default: f64) -> Sample {
+    let pick = |x: f64| if x.is_finite() { x } else { default };
+
+    Sample::stereo(pick(x.l()), pick(x.r()))
+}
+
 #[typetag::serde(tag = "type")]
 pub trait Node: Debug + Send + Sync {
     fn get_ident(&self) -> &str;
     fn get_input_labels(&self) -> &[Cow<'_, str>];
-    fn process(&self, inputs: &[Sample], phase: u64, sample_rate: u32) -> Sample;
+
+    /// Defaults to a single output.
+    fn get_output_labels(&self) -> &[Cow<'_, str>] {
+        &[Cow::Borrowed("Output")]
+    }
+
+    /// Returns the arguments that follow the ident in the [text patch format](crate::patch),
+    /// which [crate::patch::parse] passes back to construct the node.
+    fn get_args(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Returns the value of the first output.
+    fn process(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        phase: u64,
+        sample_rate: u32,
+    ) -> Sample;
+
+    /// Writes the value of each output label into `outputs`.
+    ///
+    /// Defaults to calling [Node::process] for nodes with a single output. Nodes with several
+    /// outputs must override it.
+    fn process_multi(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        outputs: &mut [Sample],
+        phase: u64,
+        sample_rate: u32,
+    ) {
+        outputs[0] = self.process(inputs, state, phase, sample_rate);
+    }
+
+    /// Returns the outside value that this node outputs, if any. Nodes with a source hold its
+    /// value in `state[0]`, which is set by
+    /// [ControlGraph::set_source](crate::control::ControlGraph::set_source) and shouldn't be
+    /// cleared by [Node::reset_state].
+    fn get_source(&self) -> Option<Source> {
+        None
+    }
+
+    /// Returns whether the outputs only depend on the inputs, and not on the phase, sample rate
+    /// or state. Nodes that are pure and have only constant inputs are evaluated once, when the
+    /// graph is optimized, rather than on every sample.
+    fn is_pure(&self) -> bool {
+        false
+    }
+
+    /// Returns the built-in operation that a [compiled](crate::control::ControlGraph::compile)
+    /// program performs in place of calling this node, if any. The operation must produce the
+    /// same outputs as [Node::process_multi], bit for bit.
+    fn opcode(&self) -> Option<Opcode> {
+        None
+    }
+
+    /// Returns the number of samples of mutable state that each instance of this node needs.
+    /// The control graph owns the state and passes it to [Node::process] as `state`.
+    fn state_len(&self, _sample_rate: u32) -> usize {
+        0
+    }
+
+    /// Returns `state` to its initial value. Called on insertion and by
+    /// [ControlGraph::reset_phase](crate::control::ControlGraph::reset_phase).
+    fn reset_state(&self, state: &mut [Sample]) {
+        state.fill(Sample::mono(0.0));
+    }
+
+    /// Processes consecutive frames at once, beginning at `phase`.
+    /// `inputs` and `outputs` are interleaved by frame: the inputs of frame `i` are
+    /// `inputs[i * n..(i + 1) * n]` and its outputs are `outputs[i * m..(i + 1) * m]`, where `n`
+    /// and `m` are the number of input and output labels.
+    ///
+    /// Defaults to calling [Node::process_multi] once per frame.
+    fn process_block(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        outputs: &mut [Sample],
+        phase: u64,
+        sample_rate: u32,
+    ) {
+        let n = self.get_input_labels().len();
+        let m = self.get_output_labels().len();
+        for (i, out) in outputs.chunks_exact_mut(m).enumerate() {
+            self.process_multi(
+                &inputs[i * n..(i + 1) * n],
+                state,
+                out,
+                phase + i as u64,
+                sample_rate,
+            );
+        }
+    }
+}
+
+/// An operation that [compiled](crate::control::ControlGraph::compile) programs perform
+/// directly, rather than by calling a node. See [Node::opcode].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Add,
+    Mul,
+    Inv,
+    Split,
+    /// The oscillators, which programs compiled to native code with the `jit` feature generate
+    /// inline. Interpreted programs still call them.
+    Sine,
+    Saw,
+    Square,
+    Triangle,
+    Pulse,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -23,7 +156,13 @@ impl Node for Empty {
         &[]
     }
 
-    fn process(&self, _inputs: &[Sample], _phase: u64, _sample_rate: u32) -> Sample {
+    fn process(
+        &self,
+        _inputs: &[Sample],
+        _state: &mut [Sample],
+        _phase: u64,
+        _sample_rate: u32,
+    ) -> Sample {
         0.0.into()
     }
 }
@@ -41,7 +180,17 @@ impl Node for ContainerInput {
         &self.0
     }
 
-    fn process(&self, inputs: &[Sample], _phase: u64, _sample_rate: u32) -> Sample {
+    fn get_args(&self) -> Vec<String> {
+        vec![self.0[0].to_string()]
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        _state: &mut [Sample],
+        _phase: u64,
+        _sample_rate: u32,
+    ) -> Sample {
         inputs[0]
     }
 }
@@ -59,28 +208,18 @@ impl Node for ContainerOutput {
         &self.0
     }
 
-    fn process(&self, inputs: &[Sample], _phase: u64, _sample_rate: u32) -> Sample {
-        inputs[0]
+    fn get_args(&self) -> Vec<String> {
+        vec![self.0[0].to_string()]
     }
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Sine;
-
-#[typetag::serde]
-impl Node for Sine {
-    fn get_ident(&self) -> &str {
-        "Sine"
-    }
-
-    fn get_input_labels(&self) -> &[Cow<'static, str>] {
-        &[Cow::Borrowed("Frequency")]
-    }
-
-    fn process(&self, inputs: &[Sample], phase: u64, sample_rate: u32) -> Sample {
-        let phase_delta = inputs[0] / (sample_rate as f64);
-
-        ((phase as f64) * phase_delta * consts::TAU).sin()
+    fn process(
+        &self,
+        inputs: &[Sample],
+        _state: &mut [Sample],
+        _phase: u64,
+        _sample_rate: u32,
+    ) -> Sample {
+        inputs[0]
     }
 }
 
@@ -93,13 +232,40 @@ impl Node for Add {
         "Add"
     }
 
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn opcode(&self) -> Option<Opcode> {
+        Some(Opcode::Add)
+    }
+
     fn get_input_labels<'a>(&self) -> &[Cow<'static, str>] {
         &[Cow::Borrowed("LHS"), Cow::Borrowed("RHS")]
     }
 
-    fn process(&self, inputs: &[Sample], _phase: u64, _sample_rate: u32) -> Sample {
+    fn process(
+        &self,
+        inputs: &[Sample],
+        _state: &mut [Sample],
+        _phase: u64,
+        _sample_rate: u32,
+    ) -> Sample {
         inputs[0] + inputs[1]
     }
+
+    fn process_block(
+        &self,
+        inputs: &[Sample],
+        _state: &mut [Sample],
+        outputs: &mut [Sample],
+        _phase: u64,
+        _sample_rate: u32,
+    ) {
+        for (out, i) in outputs.iter_mut().zip(inputs.chunks_exact(2)) {
+            *out = i[0] + i[1];
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -111,13 +277,40 @@ impl Node for Mul {
         "Multiply"
     }
 
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn opcode(&self) -> Option<Opcode> {
+        Some(Opcode::Mul)
+    }
+
     fn get_input_labels<'a>(&self) -> &[Cow<'static, str>] {
         &[Cow::Borrowed("LHS"), Cow::Borrowed("RHS")]
     }
 
-    fn process(&self, inputs: &[Sample], _phase: u64, _sample_rate: u32) -> Sample {
+    fn process(
+        &self,
+        inputs: &[Sample],
+        _state: &mut [Sample],
+        _phase: u64,
+        _sample_rate: u32,
+    ) -> Sample {
         inputs[0] * inputs[1]
     }
+
+    fn process_block(
+        &self,
+        inputs: &[Sample],
+        _state: &mut [Sample],
+        outputs: &mut [Sample],
+        _phase: u64,
+        _sample_rate: u32,
+    ) {
+        for (out, i) in outputs.iter_mut().zip(inputs.chunks_exact(2)) {
+            *out = i[0] * i[1];
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -129,11 +322,25 @@ impl Node for Inv {
         "Inverse"
     }
 
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn opcode(&self) -> Option<Opcode> {
+        Some(Opcode::Inv)
+    }
+
     fn get_input_labels<'a>(&self) -> &[Cow<'static, str>] {
         &[Cow::Borrowed("Input")]
     }
 
-    fn process(&self, inputs: &[Sample], _phase: u64, _sample_rate: u32) -> Sample {
+    fn process(
+        &self,
+        inputs: &[Sample],
+        _state: &mut [Sample],
+        _phase: u64,
+        _sample_rate: u32,
+    ) -> Sample {
         inputs[0].recip()
     }
 }
@@ -151,9 +358,35 @@ impl Node for Const {
         &[]
     }
 
-    fn process(&self, _inputs: &[Sample], _phase: u64, _sample_rate: u32) -> Sample {
+    /// Mono constants only need one argument.
+    fn get_args(&self) -> Vec<String> {
+        if self.0.l() == self.0.r() {
+            vec![self.0.l().to_string()]
+        } else {
+            vec![self.0.l().to_string(), self.0.r().to_string()]
+        }
+    }
+
+    fn process(
+        &self,
+        _inputs: &[Sample],
+        _state: &mut [Sample],
+        _phase: u64,
+        _sample_rate: u32,
+    ) -> Sample {
         self.0
     }
+
+    fn process_block(
+        &self,
+        _inputs: &[Sample],
+        _state: &mut [Sample],
+        outputs: &mut [Sample],
+        _phase: u64,
+        _sample_rate: u32,
+    ) {
+        outputs.fill(self.0);
+    }
 }
 
 impl From<Sample> for Const {
@@ -165,3 +398,117 @@ impl From<Sample> for Const {
 pub fn c(sample: f64) -> Const {
     Const(sample.into())
 }
+
+/// Splits a stereo sample into two mono samples.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Split;
+
+#[typetag::serde]
+impl Node for Split {
+    fn get_ident(&self) -> &str {
+        "Split"
+    }
+
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn opcode(&self) -> Option<Opcode> {
+        Some(Opcode::Split)
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &[Cow::Borrowed("Input")]
+    }
+
+    fn get_output_labels(&self) -> &[Cow<'static, str>] {
+        &[Cow::Borrowed("Left"), Cow::Borrowed("Right")]
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        _state: &mut [Sample],
+        _phase: u64,
+        _sample_rate: u32,
+    ) -> Sample {
+        Sample::mono(inputs[0].l())
+    }
+
+    fn process_multi(
+        &self,
+        inputs: &[Sample],
+        _state: &mut [Sample],
+        outputs: &mut [Sample],
+        _phase: u64,
+        _sample_rate: u32,
+    ) {
+        outputs[0] = Sample::mono(inputs[0].l());
+        outputs[1] = Sample::mono(inputs[0].r());
+    }
+}
+
+/// Outputs what was pushed into it `delay` samples ago, which lets the graph feed a node's output
+/// back into its own inputs. Inserted by
+/// [ControlGraph::connect_feedback](crate::control::ControlGraph::connect_feedback), which
+/// pushes the source's output after every sample.
+///
+/// The state holds the position of the next write, followed by the `delay` buffered samples.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Feedback {
+    pub delay: usize,
+}
+
+impl Feedback {
+    /// Writes `val` over the oldest sample in `state`, which is read again in `delay` samples.
+    pub(crate) fn push(state: &mut [Sample], val: Sample) {
+        let delay = state.len() - 1;
+        let pos = state[0].l() as usize;
+        state[1 + pos] = val;
+        state[0] = Sample::mono(((pos + 1) % delay) as f64);
+    }
+}
+
+#[typetag::serde]
+impl Node for Feedback {
+    fn get_ident(&self) -> &str {
+        "Feedback"
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &[]
+    }
+
+    fn get_args(&self) -> Vec<String> {
+        vec![self.delay.to_string()]
+    }
+
+    fn state_len(&self, _sample_rate: u32) -> usize {
+        self.delay + 1
+    }
+
+    fn process(
+        &self,
+        _inputs: &[Sample],
+        state: &mut [Sample],
+        _phase: u64,
+        _sample_rate: u32,
+    ) -> Sample {
+        state[1 + state[0].l() as usize]
+    }
+
+    fn process_block(
+        &self,
+        _inputs: &[Sample],
+        state: &mut [Sample],
+        outputs: &mut [Sample],
+        _phase: u64,
+        _sample_rate: u32,
+    ) {
+        // blocks are never longer than `delay`, so none of these are pushed until afterwards
+        let pos = state[0].l() as usize;
+        for (i, out) in outputs.iter_mut().enumerate() {
+            *out = state[1 + (pos + i) % self.delay];
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::node::Node;
+use crate::Sample;
+
+/// Returns the number of samples that a delay of up to `max_time` seconds needs to look back.
+fn max_samples(max_time: f64, sample_rate: u32) -> usize {
+    (max_time.max(0.0) * sample_rate as f64).ceil() as usize
+}
+
+/// A circular buffer of past inputs, which is the end of a delay's state after `header` samples
+/// of other state. `state[0]` holds the position of the last write.
+struct Line<'a> {
+    buffer: &'a mut [Sample],
+    pos: usize,
+}
+
+impl<'a> Line<'a> {
+    fn new(state: &'a mut [Sample], header: usize) -> (Self, &'a mut [Sample]) {
+        let pos = state[0].l() as usize;
+        let (header, buffer) = state.split_at_mut(header);
+
+        (Self { buffer, pos }, header)
+    }
+
+    /// Writes `x` over the oldest sample, which becomes the sample delayed by 0.
+    fn push(&mut self, x: Sample) {
+        self.pos = (self.pos + 1) % self.buffer.len();
+        self.buffer[self.pos] = x;
+    }
+
+    /// Returns the sample written `delay` samples ago, on channel `lane`.
+    fn get(&self, delay: usize, lane: usize) -> f64 {
+        let len = self.buffer.len();
+        let s = self.buffer[(self.pos + len - delay) % len];
+
+        if lane == 0 {
+            s.l()
+        } else {
+            s.r()
+        }
+    }
+}
+
+/// Outputs its input from `Time` seconds ago, rounded to the nearest sample. Delays longer than
+/// `max_time` seconds are shortened to it.
+///
+/// The state holds the position of the last write, followed by enough past inputs for
+/// `max_time`, so it's cleared whenever the sample rate changes.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Delay {
+    pub max_time: f64,
+}
+
+#[typetag::serde]
+impl Node for Delay {
+    fn get_ident(&self) -> &str {
+        "Delay"
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &[Cow::Borrowed("Input"), Cow::Borrowed("Time")]
+    }
+
+    fn get_args(&self) -> Vec<String> {
+        vec![self.max_time.to_string()]
+    }
+
+    fn state_len(&self, sample_rate: u32) -> usize {
+        1 + max_samples(self.max_time, sample_rate) + 1
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        _phase: u64,
+        sample_rate: u32,
+    ) -> Sample {
+        let max = max_samples(self.max_time, sample_rate) as f64;
+        // `max` also replaces a missing time with 0
+        let delay = |time: f64| (time * sample_rate as f64).round().max(0.0).min(max) as usize;
+
+        let (mut line, header) = Line::new(state, 1);
+        line.push(inputs[0]);
+        header[0] = Sample::mono(line.pos as f64);
+
+        Sample::stereo(
+            line.get(delay(inputs[1].l()), 0),
+            line.get(delay(inputs[1].r()), 1),
+        )
+    }
+}
+
+/// How a [FractionalDelay] reads between samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interpolation {
+    /// Cheap, but dulls the high end for delays halfway between samples.
+    Linear,
+    /// Cubic Hermite, which keeps more of the high end.
+    Cubic,
+    /// A first-order allpass filter, which keeps the whole spectrum, but smears fast changes in
+    /// the delay time.
+    Allpass,
+}
+
+impl Interpolation {
+    pub const ALL: [Interpolation; 3] = [
+        Interpolation::Linear,
+        Interpolation::Cubic,
+        Interpolation::Allpass,
+    ];
+
+    /// Returns the name of the interpolation in the [text patch format](crate::patch).
+    pub fn name(&self) -> &'static str {
+        match self {
+            Interpolation::Linear => "Linear",
+            Interpolation::Cubic => "Cubic",
+            Interpolation::Allpass => "Allpass",
+        }
+    }
+}
+
+/// Outputs its input from `Time` seconds ago, interpolating between samples so that the time
+/// can be modulated smoothly. The delay is kept between one sample and `max_time` seconds.
+///
+/// The state holds the position of the last write and the last output, which the allpass
+/// interpolation feeds back, followed by enough past inputs for `max_time`, so it's cleared
+/// whenever the sample rate changes.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FractionalDelay {
+    pub max_time: f64,
+    pub interpolation: Interpolation,
+}
+
+impl FractionalDelay {
+    /// Reads `delay` samples back on channel `lane`, where `last` is the last output.
+    fn read(&self, line: &Line, delay: f64, lane: usize, last: f64) -> f64 {
+        let n = delay.floor() as usize;
+        let t = delay - delay.floor();
+
+        match self.interpolation {
+            Interpolation::Linear => {
+                let (a, b) = (line.get(n, lane), line.get(n + 1, lane));
+                a + (b - a) * t
+            }
+            Interpolation::Cubic => {
+                let (x0, x1, x2, x3) = (
+                    line.get(n - 1, lane),
+                    line.get(n, lane),
+                    line.get(n + 1, lane),
+                    line.get(n + 2, lane),
+                );
+
+                let c1 = 0.5 * (x2 - x0);
+                let c2 = x0 - 2.5 * x1 + 2.0 * x2 - 0.5 * x3;
+                let c3 = 0.5 * (x3 - x0) + 1.5 * (x1 - x2);
+                ((c3 * t + c2) * t + c1) * t + x1
+            }
+            Interpolation::Allpass => {
+                // keeps the fractional part between 0.5 and 1.5, where the filter's coefficient
+                // is small and its pole is far from the unit circle
+                let n = (delay - 0.5).floor() as usize;
+                let t = delay - n as f64;
+                let eta = (1.0 - t) / (1.0 + t);
+
+                eta * line.get(n, lane) + line.get(n + 1, lane) - eta * last
+            }
+        }
+    }
+}
+
+#[typetag::serde]
+impl Node for FractionalDelay {
+    fn get_ident(&self) -> &str {
+        "FractionalDelay"
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &[Cow::Borrowed("Input"), Cow::Borrowed("Time")]
+    }
+
+    fn get_args(&self) -> Vec<String> {
+        vec![
+            self.max_time.to_string(),
+            self.interpolation.name().to_string(),
+        ]
+    }
+
+    fn state_len(&self, sample_rate: u32) -> usize {
+        // cubic interpolation reads one sample past the longest delay, and one more for its
+        // fractional part
+        2 + max_samples(self.max_time, sample_rate) + 3
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        _phase: u64,
+        sample_rate: u32,
+    ) -> Sample {
+        let max = max_samples(self.max_time, sample_rate).max(1) as f64;
+        // `max` also replaces a missing time with 1
+        let delay = |time: f64| (time * sample_rate as f64).max(1.0).min(max);
+
+        let (mut line, header) = Line::new(state, 2);
+        line.push(inputs[0]);
+        header[0] = Sample::mono(line.pos as f64);
+
+        let last = header[1];
+        let out = Sample::stereo(
+            self.read(&line, delay(inputs[1].l()), 0, last.l()),
+            self.read(&line, delay(inputs[1].r()), 1, last.r()),
+        );
+        header[1] = out;
+
+        out
+    }
+}
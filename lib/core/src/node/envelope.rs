@@ -0,0 +1,425 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::EnvelopeError;
+use crate::node::{or_default, Node};
+use crate::Sample;
+
+/// How sharply [Curve::Exponential] stages bend. The level covers `1 - e^-CURVATURE` of the
+/// distance to its target over the stage before it's rescaled to land on the target.
+const CURVATURE: f64 = 5.0;
+
+/// The shape of each stage of an envelope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Curve {
+    Linear,
+    /// Moves quickly at first, then eases into the target, like an analog envelope.
+    Exponential,
+}
+
+impl Curve {
+    pub const ALL: [Curve; 2] = [Curve::Linear, Curve::Exponential];
+
+    /// Returns the name of the curve in the [text patch format](crate::patch).
+    pub fn name(&self) -> &'static str {
+        match self {
+            Curve::Linear => "Linear",
+            Curve::Exponential => "Exponential",
+        }
+    }
+
+    /// Returns how far along to its target the level is, `progress` (0 to 1) through a stage.
+    fn shape(&self, progress: f64) -> f64 {
+        match self {
+            Curve::Linear => progress,
+            Curve::Exponential => {
+                (1.0 - (-CURVATURE * progress).exp()) / (1.0 - (-CURVATURE).exp())
+            }
+        }
+    }
+}
+
+/// What an envelope does when its gate opens again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Trigger {
+    /// Restarts from 0.
+    Retrigger,
+    /// Restarts from the current level, so that overlapping notes don't click.
+    Legato,
+}
+
+impl Trigger {
+    pub const ALL: [Trigger; 2] = [Trigger::Retrigger, Trigger::Legato];
+
+    /// Returns the name of the trigger mode in the [text patch format](crate::patch).
+    pub fn name(&self) -> &'static str {
+        match self {
+            Trigger::Retrigger => "Retrigger",
+            Trigger::Legato => "Legato",
+        }
+    }
+}
+
+/// The settings shared by every channel of an envelope.
+struct Stages<'a> {
+    /// The time (in seconds) and target level of each stage.
+    stages: &'a dyn Fn(usize) -> (f64, f64),
+    len: usize,
+    /// The stage that holds its level while the gate is open.
+    sustain: Option<usize>,
+    curve: Curve,
+    trigger: Trigger,
+    sample_rate: u32,
+}
+
+/// The state of one channel of an envelope.
+struct Channel {
+    /// The current stage plus one, or 0 before the envelope is first triggered.
+    stage: f64,
+    /// How far through the current stage the envelope is, from 0 to 1.
+    progress: f64,
+    /// The level that the current stage began at.
+    start: f64,
+    level: f64,
+    gate: f64,
+}
+
+impl Stages<'_> {
+    /// Advances the envelope in `state` by one sample, on channel `lane`.
+    ///
+    /// Returns the level, and whether the last stage has just ended.
+    fn process(&self, state: &mut [Sample], lane: usize, gate: f64) -> (f64, bool) {
+        let mut ch = Channel {
+            stage: get(state[0], lane),
+            progress: get(state[1], lane),
+            start: get(state[2], lane),
+            level: get(state[3], lane),
+            gate: get(state[4], lane),
+        };
+        let end = self.step(&mut ch, gate > 0.0);
+
+        let vals = [ch.stage, ch.progress, ch.start, ch.level, gate];
+        for (s, val) in state.iter_mut().zip(vals) {
+            *get_mut(s, lane) = val;
+        }
+
+        (ch.level, end)
+    }
+
+    fn step(&self, ch: &mut Channel, gate: bool) -> bool {
+        let was_open = ch.gate > 0.0;
+        let active = ch.stage > 0.0 && (ch.stage as usize) <= self.len;
+
+        if gate && !was_open {
+            if self.trigger == Trigger::Retrigger {
+                ch.level = 0.0;
+            }
+
+            ch.stage = 1.0;
+            ch.progress = 0.0;
+            ch.start = ch.level;
+        } else if !gate && was_open && active {
+            // skip to the release, even if the sustain hasn't been reached yet
+            if let Some(sustain) = self.sustain.filter(|&s| (ch.stage as usize) <= s + 1) {
+                ch.stage = (sustain + 2) as f64;
+                ch.progress = 0.0;
+                ch.start = ch.level;
+
+                if sustain + 1 == self.len {
+                    return true;
+                }
+            }
+        }
+
+        let stage = ch.stage as usize;
+        if stage == 0 || stage > self.len {
+            return false;
+        }
+
+        let i = stage - 1;
+        let (time, target) = (self.stages)(i);
+
+        // stages without a positive time end right away
+        let step = if time > 0.0 {
+            (time * self.sample_rate as f64).recip().min(1.0)
+        } else {
+            1.0
+        };
+        ch.progress = (ch.progress + step).min(1.0);
+
+        // stages a whole number of samples long end on time, despite rounding
+        if 1.0 - ch.progress < 1e-9 {
+            ch.progress = 1.0;
+        }
+        ch.level = ch.start + (target - ch.start) * self.curve.shape(ch.progress);
+
+        if ch.progress < 1.0 || (gate && self.sustain == Some(i)) {
+            return false;
+        }
+
+        ch.stage += 1.0;
+        ch.progress = 0.0;
+        ch.start = target;
+
+        stage == self.len
+    }
+}
+
+fn get(s: Sample, lane: usize) -> f64 {
+    if lane == 0 {
+        s.l()
+    } else {
+        s.r()
+    }
+}
+
+fn get_mut(s: &mut Sample, lane: usize) -> &mut f64 {
+    if lane == 0 {
+        s.l_mut()
+    } else {
+        s.r_mut()
+    }
+}
+
+/// The number of samples of state that an envelope needs: see [Channel].
+const ENVELOPE_STATE_LEN: usize = 5;
+
+/// An attack, decay, sustain and release envelope, from 0 up to 1, down to `Sustain` while the
+/// gate is open, and back down to 0 once it closes. Times are in seconds, and are read while
+/// their stage runs, so they can be modulated. Unconnected times are 0, and an unconnected
+/// `Sustain` is 1.
+///
+/// `End` is 1 for the sample that the release finishes on, and 0 otherwise.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Adsr {
+    pub curve: Curve,
+    pub trigger: Trigger,
+}
+
+#[typetag::serde]
+impl Node for Adsr {
+    fn get_ident(&self) -> &str {
+        "Adsr"
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &[
+            Cow::Borrowed("Gate"),
+            Cow::Borrowed("Attack"),
+            Cow::Borrowed("Decay"),
+            Cow::Borrowed("Sustain"),
+            Cow::Borrowed("Release"),
+        ]
+    }
+
+    fn get_output_labels(&self) -> &[Cow<'static, str>] {
+        &[Cow::Borrowed("Output"), Cow::Borrowed("End")]
+    }
+
+    fn get_args(&self) -> Vec<String> {
+        vec![
+            self.curve.name().to_string(),
+            self.trigger.name().to_string(),
+        ]
+    }
+
+    fn state_len(&self, _sample_rate: u32) -> usize {
+        ENVELOPE_STATE_LEN
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        phase: u64,
+        sample_rate: u32,
+    ) -> Sample {
+        let mut outputs = [Sample::default(); 2];
+        self.process_multi(inputs, state, &mut outputs, phase, sample_rate);
+
+        outputs[0]
+    }
+
+    fn process_multi(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        outputs: &mut [Sample],
+        _phase: u64,
+        sample_rate: u32,
+    ) {
+        let [attack, decay, sustain, release] = [
+            or_default(inputs[1], 0.0),
+            or_default(inputs[2], 0.0),
+            or_default(inputs[3], 1.0),
+            or_default(inputs[4], 0.0),
+        ];
+
+        for lane in 0..2 {
+            let stages = |i: usize| match i {
+                0 => (get(attack, lane), 1.0),
+                1 => (get(decay, lane), get(sustain, lane)),
+                _ => (get(release, lane), 0.0),
+            };
+
+            let (level, end) = Stages {
+                stages: &stages,
+                len: 3,
+                sustain: Some(1),
+                curve: self.curve,
+                trigger: self.trigger,
+                sample_rate,
+            }
+            .process(state, lane, get(inputs[0], lane));
+
+            *get_mut(&mut outputs[0], lane) = level;
+            *get_mut(&mut outputs[1], lane) = end as u8 as f64;
+        }
+    }
+}
+
+/// An envelope made of any number of stages, each of which moves from the level that the last
+/// one ended on to its `Level` input over its `Time` input (in seconds). Opening the gate starts
+/// the first stage.
+///
+/// While the gate is open, the level holds at the end of the `sustain` stage, if there is one.
+/// Closing the gate skips ahead to the stage after it. Without a sustain stage, the envelope runs
+/// through every stage whatever the gate does.
+///
+/// `End` is 1 for the sample that the last stage finishes on, and 0 otherwise.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(try_from = "MultiStageEnvelopeFields")]
+pub struct MultiStageEnvelope {
+    stages: usize,
+    sustain: Option<usize>,
+    curve: Curve,
+    trigger: Trigger,
+    labels: Vec<Cow<'static, str>>,
+}
+
+impl MultiStageEnvelope {
+    /// Creates an envelope with `stages` stages, which holds at stage `sustain` (counting from 0)
+    /// while the gate is open.
+    ///
+    /// Returns an error if there are no stages, or `sustain` is past the last stage.
+    pub fn new(
+        stages: usize,
+        sustain: Option<usize>,
+        curve: Curve,
+        trigger: Trigger,
+    ) -> Result<Self, EnvelopeError> {
+        if stages == 0 {
+            return Err(EnvelopeError::NoStages);
+        }
+        if let Some(sustain) = sustain.filter(|&sustain| sustain >= stages) {
+            return Err(EnvelopeError::SustainPastEnd { sustain, stages });
+        }
+
+        let labels = std::iter::once(Cow::Borrowed("Gate"))
+            .chain((0..stages).flat_map(|i| {
+                [
+                    Cow::Owned(format!("Time {i}")),
+                    Cow::Owned(format!("Level {i}")),
+                ]
+            }))
+            .collect();
+
+        Ok(Self {
+            stages,
+            sustain,
+            curve,
+            trigger,
+            labels,
+        })
+    }
+}
+
+/// The serialized fields of a [MultiStageEnvelope], which are deserialized through
+/// [MultiStageEnvelope::new] so that saved data is checked like any other. The saved labels are
+/// rebuilt rather than trusted.
+#[derive(Deserialize)]
+struct MultiStageEnvelopeFields {
+    stages: usize,
+    sustain: Option<usize>,
+    curve: Curve,
+    trigger: Trigger,
+    #[serde(rename = "labels")]
+    _labels: Vec<Cow<'static, str>>,
+}
+
+impl TryFrom<MultiStageEnvelopeFields> for MultiStageEnvelope {
+    type Error = EnvelopeError;
+
+    fn try_from(fields: MultiStageEnvelopeFields) -> Result<Self, Self::Error> {
+        Self::new(fields.stages, fields.sustain, fields.curve, fields.trigger)
+    }
+}
+
+#[typetag::serde]
+impl Node for MultiStageEnvelope {
+    fn get_ident(&self) -> &str {
+        "MultiStageEnvelope"
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &self.labels
+    }
+
+    fn get_output_labels(&self) -> &[Cow<'static, str>] {
+        &[Cow::Borrowed("Output"), Cow::Borrowed("End")]
+    }
+
+    fn get_args(&self) -> Vec<String> {
+        vec![
+            self.stages.to_string(),
+            self.sustain
+                .map_or("none".to_string(), |sustain| sustain.to_string()),
+            self.curve.name().to_string(),
+            self.trigger.name().to_string(),
+        ]
+    }
+
+    fn state_len(&self, _sample_rate: u32) -> usize {
+        ENVELOPE_STATE_LEN
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        phase: u64,
+        sample_rate: u32,
+    ) -> Sample {
+        let mut outputs = [Sample::default(); 2];
+        self.process_multi(inputs, state, &mut outputs, phase, sample_rate);
+
+        outputs[0]
+    }
+
+    fn process_multi(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        outputs: &mut [Sample],
+        _phase: u64,
+        sample_rate: u32,
+    ) {
+        for lane in 0..2 {
+            let stages = |i: usize| (get(inputs[1 + 2 * i], lane), get(inputs[2 + 2 * i], lane));
+
+            let (level, end) = Stages {
+                stages: &stages,
+                len: self.stages,
+                sustain: self.sustain,
+                curve: self.curve,
+                trigger: self.trigger,
+                sample_rate,
+            }
+            .process(state, lane, get(inputs[0], lane));
+
+            *get_mut(&mut outputs[0], lane) = level;
+            *get_mut(&mut outputs[1], lane) = end as u8 as f64;
+        }
+    }
+}
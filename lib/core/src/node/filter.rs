@@ -0,0 +1,271 @@
+use std::{borrow::Cow, f64::consts};
+
+use serde::{Deserialize, Serialize};
+
+use crate::node::{or_default, Node};
+use crate::Sample;
+
+/// Returns the prewarped integrator gain `g = tan(π · cutoff / sample_rate)` of a
+/// bilinear-transformed filter, so that its response at `cutoff` matches the analog prototype.
+///
+/// The cutoff is clamped below Nyquist, where `g` blows up, so that modulating it anywhere is safe.
+fn prewarp(cutoff: Sample, sample_rate: u32) -> Sample {
+    let nyquist = sample_rate as f64 / 2.0;
+    let w = cutoff.clamp(0.0, nyquist * 0.99) * (consts::PI / sample_rate as f64);
+
+    Sample::stereo(w.l().tan(), w.r().tan())
+}
+
+/// The response of a [Biquad].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BiquadMode {
+    Lowpass,
+    Highpass,
+    /// Band-pass with a peak gain of 0 dB.
+    Bandpass,
+    Notch,
+    /// Boosts or cuts around the cutoff by `Gain` dB.
+    Peak,
+    /// Boosts or cuts below the cutoff by `Gain` dB.
+    LowShelf,
+    /// Boosts or cuts above the cutoff by `Gain` dB.
+    HighShelf,
+}
+
+impl BiquadMode {
+    pub const ALL: [BiquadMode; 7] = [
+        BiquadMode::Lowpass,
+        BiquadMode::Highpass,
+        BiquadMode::Bandpass,
+        BiquadMode::Notch,
+        BiquadMode::Peak,
+        BiquadMode::LowShelf,
+        BiquadMode::HighShelf,
+    ];
+
+    /// Returns the name of the mode in the [text patch format](crate::patch).
+    pub fn name(&self) -> &'static str {
+        match self {
+            BiquadMode::Lowpass => "Lowpass",
+            BiquadMode::Highpass => "Highpass",
+            BiquadMode::Bandpass => "Bandpass",
+            BiquadMode::Notch => "Notch",
+            BiquadMode::Peak => "Peak",
+            BiquadMode::LowShelf => "LowShelf",
+            BiquadMode::HighShelf => "HighShelf",
+        }
+    }
+}
+
+/// A second-order filter with the responses of the RBJ audio EQ cookbook. `Q` sets the
+/// resonance, or the width of the band for band-pass, notch and peak filters, and `Gain` (in dB)
+/// only affects peak and shelf filters. While unconnected, `Q` is 1/√2, which is flat up to the
+/// cutoff, and `Gain` is 0 dB.
+///
+/// Rather than in direct form, whose state blows up when its coefficients change quickly, each
+/// response is mixed from the outputs of a [Svf]. Both come from the same bilinear transform, so
+/// the response is the same, but the filter stays stable under audio-rate modulation.
+///
+/// The state is that of the [Svf].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Biquad(pub BiquadMode);
+
+#[typetag::serde]
+impl Node for Biquad {
+    fn get_ident(&self) -> &str {
+        "Biquad"
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &[
+            Cow::Borrowed("Input"),
+            Cow::Borrowed("Frequency"),
+            Cow::Borrowed("Q"),
+            Cow::Borrowed("Gain"),
+        ]
+    }
+
+    fn get_args(&self) -> Vec<String> {
+        vec![self.0.name().to_string()]
+    }
+
+    fn state_len(&self, _sample_rate: u32) -> usize {
+        2
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        _phase: u64,
+        sample_rate: u32,
+    ) -> Sample {
+        let x = inputs[0];
+        let g = prewarp(inputs[1], sample_rate);
+        let k = or_default(inputs[2], consts::FRAC_1_SQRT_2)
+            .clamp(1e-3, f64::MAX)
+            .recip();
+        let a = (or_default(inputs[3], 0.0) * (consts::LN_10 / 40.0)).exp();
+
+        let (zero, one) = (Sample::mono(0.0), Sample::mono(1.0));
+        let (g, k, [m0, m1, m2]) = match self.0 {
+            BiquadMode::Lowpass => (g, k, [zero, zero, one]),
+            BiquadMode::Highpass => (g, k, [one, -k, -one]),
+            BiquadMode::Bandpass => (g, k, [zero, k, zero]),
+            BiquadMode::Notch => (g, k, [one, -k, zero]),
+            BiquadMode::Peak => {
+                let k = k / a;
+                (g, k, [one, k * (a * a - one), zero])
+            }
+            BiquadMode::LowShelf => (g / a.sqrt(), k, [one, k * (a - one), a * a - one]),
+            BiquadMode::HighShelf => (g * a.sqrt(), k, [a * a, k * (one - a) * a, one - a * a]),
+        };
+
+        let (band, low) = svf(x, g, k, state);
+
+        m0 * x + m1 * band + m2 * low
+    }
+}
+
+/// Advances a [Svf] with integrator gain `g` and damping `k` by one sample of `x`.
+///
+/// Returns the band-pass and low-pass outputs.
+fn svf(x: Sample, g: Sample, k: Sample, state: &mut [Sample]) -> (Sample, Sample) {
+    // solves the instantaneous feedback of both integrators
+    let a1 = (g * (g + k) + 1.0).recip();
+    let a2 = g * a1;
+    let a3 = g * a2;
+
+    let v3 = x - state[1];
+    let band = a1 * state[0] + a2 * v3;
+    let low = state[1] + a2 * state[0] + a3 * v3;
+
+    state[0] = band * 2.0 - state[0];
+    state[1] = low * 2.0 - state[1];
+
+    (band, low)
+}
+
+/// A topology-preserving transform state-variable filter, which outputs its low-pass, band-pass
+/// and high-pass responses at once. `Resonance` goes from 0 to 1, where it self-oscillates.
+///
+/// Its state is the charge of two integrators, which cutoff changes don't disturb, so it stays
+/// stable under audio-rate modulation.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Svf;
+
+#[typetag::serde]
+impl Node for Svf {
+    fn get_ident(&self) -> &str {
+        "Svf"
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &[
+            Cow::Borrowed("Input"),
+            Cow::Borrowed("Cutoff"),
+            Cow::Borrowed("Resonance"),
+        ]
+    }
+
+    fn get_output_labels(&self) -> &[Cow<'static, str>] {
+        &[
+            Cow::Borrowed("Lowpass"),
+            Cow::Borrowed("Bandpass"),
+            Cow::Borrowed("Highpass"),
+        ]
+    }
+
+    fn state_len(&self, _sample_rate: u32) -> usize {
+        2
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        phase: u64,
+        sample_rate: u32,
+    ) -> Sample {
+        let mut outputs = [Sample::default(); 3];
+        self.process_multi(inputs, state, &mut outputs, phase, sample_rate);
+
+        outputs[0]
+    }
+
+    fn process_multi(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        outputs: &mut [Sample],
+        _phase: u64,
+        sample_rate: u32,
+    ) {
+        let g = prewarp(inputs[1], sample_rate);
+        let k = (Sample::mono(1.0) - inputs[2].clamp(0.0, 1.0)) * 2.0;
+
+        let x = inputs[0];
+        let (band, low) = svf(x, g, k, state);
+
+        outputs[0] = low;
+        outputs[1] = band;
+        outputs[2] = x - k * band - low;
+    }
+}
+
+/// A 4-pole (24 dB/octave) low-pass ladder filter, built from four topology-preserving
+/// transform one-pole stages with the feedback solved without a unit delay. `Resonance` goes
+/// from 0 to 1, where it self-oscillates, and lowers the gain of the pass band as it rises.
+///
+/// The state is the charge of each stage's integrator.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Ladder;
+
+#[typetag::serde]
+impl Node for Ladder {
+    fn get_ident(&self) -> &str {
+        "Ladder"
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &[
+            Cow::Borrowed("Input"),
+            Cow::Borrowed("Cutoff"),
+            Cow::Borrowed("Resonance"),
+        ]
+    }
+
+    fn state_len(&self, _sample_rate: u32) -> usize {
+        4
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        _phase: u64,
+        sample_rate: u32,
+    ) -> Sample {
+        let g = prewarp(inputs[1], sample_rate);
+        let k = inputs[2].clamp(0.0, 1.0) * 4.0;
+
+        // each stage outputs `gain * x + s / (1 + g)`, so the last one is a linear function of
+        // the input to the first, which resolves the feedback
+        let one = Sample::mono(1.0);
+        let gain = g / (g + one);
+        let s = state
+            .iter()
+            .fold(Sample::mono(0.0), |acc, &s| acc * gain + s);
+        let s = s / (g + one);
+        let gain4 = gain * gain * gain * gain;
+
+        let mut x = (inputs[0] - k * s) / (k * gain4 + one);
+        for s in state.iter_mut() {
+            let v = (x - *s) * gain;
+            x = v + *s;
+            *s = x + v;
+        }
+
+        x
+    }
+}
@@ -0,0 +1,189 @@
+use std::{borrow::Cow, f64::consts};
+
+use serde::{Deserialize, Serialize};
+
+use crate::node::{or_default, Node};
+use crate::Sample;
+
+/// The waveform of an [Lfo].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LfoShape {
+    Sine,
+    /// Rises from 0 to its peak over the first quarter of each cycle, in phase with the sine.
+    Triangle,
+    /// Rises from its minimum to its peak over each cycle.
+    Saw,
+    /// At its peak for the first half of each cycle, and at its minimum for the second.
+    Square,
+    /// Holds a new random value for each cycle.
+    SampleAndHold,
+    /// Glides from one random value to the next over each cycle.
+    SmoothRandom,
+}
+
+impl LfoShape {
+    pub const ALL: [LfoShape; 6] = [
+        LfoShape::Sine,
+        LfoShape::Triangle,
+        LfoShape::Saw,
+        LfoShape::Square,
+        LfoShape::SampleAndHold,
+        LfoShape::SmoothRandom,
+    ];
+
+    /// Returns the name of the shape in the [text patch format](crate::patch).
+    pub fn name(&self) -> &'static str {
+        match self {
+            LfoShape::Sine => "Sine",
+            LfoShape::Triangle => "Triangle",
+            LfoShape::Saw => "Saw",
+            LfoShape::Square => "Square",
+            LfoShape::SampleAndHold => "SampleAndHold",
+            LfoShape::SmoothRandom => "SmoothRandom",
+        }
+    }
+
+    /// Returns the value from -1 to 1, `t` (0 to 1) through cycle number `cycle`.
+    fn value(&self, t: f64, cycle: f64) -> f64 {
+        match self {
+            LfoShape::Sine => (t * consts::TAU).sin(),
+            LfoShape::Triangle => {
+                let t = t + 0.25;
+                1.0 - 4.0 * (t - t.floor() - 0.5).abs()
+            }
+            LfoShape::Saw => 2.0 * t - 1.0,
+            LfoShape::Square => {
+                if t < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            LfoShape::SampleAndHold => random(cycle),
+            LfoShape::SmoothRandom => {
+                let (from, to) = (random(cycle - 1.0), random(cycle));
+                from + (to - from) * (1.0 - (t * consts::PI).cos()) / 2.0
+            }
+        }
+    }
+}
+
+/// Returns a value from -1 to 1 that only depends on `cycle`, so that random shapes don't need to
+/// keep a generator in their state and restart the same way after a reset.
+fn random(cycle: f64) -> f64 {
+    // splitmix64
+    let mut x = (cycle as i64 as u64).wrapping_add(0x9E3779B97F4A7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+    x ^= x >> 31;
+
+    (x >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+}
+
+/// The range that an [Lfo] outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Polarity {
+    /// From 0 to 1.
+    Unipolar,
+    /// From -1 to 1.
+    Bipolar,
+}
+
+impl Polarity {
+    pub const ALL: [Polarity; 2] = [Polarity::Unipolar, Polarity::Bipolar];
+
+    /// Returns the name of the polarity in the [text patch format](crate::patch).
+    pub fn name(&self) -> &'static str {
+        match self {
+            Polarity::Unipolar => "Unipolar",
+            Polarity::Bipolar => "Bipolar",
+        }
+    }
+}
+
+/// A low-frequency oscillator for modulating other nodes. Unlike the oscillators, it isn't
+/// band-limited, so its corners stay sharp.
+///
+/// `Rate` is in Hz, unless `Tempo` is connected to a positive tempo in beats per minute, such as
+/// that of a [Tempo](crate::node::Tempo) node. Then the rate is locked to the tempo, in cycles
+/// per beat: 1 for quarter notes in 4/4, 0.25 for one cycle per bar, or 3 for eighth-note
+/// triplets. While `Tempo` is unconnected, or isn't a positive number, the Lfo runs freely.
+/// `Phase` shifts the waveform by a fraction of a cycle. An unconnected `Rate` is 0, which holds
+/// the Lfo still, and an unconnected `Phase` is 0.
+///
+/// The state holds the phase from 0 to 1, and the number of cycles that have passed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Lfo {
+    pub shape: LfoShape,
+    pub polarity: Polarity,
+}
+
+#[typetag::serde]
+impl Node for Lfo {
+    fn get_ident(&self) -> &str {
+        "Lfo"
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &[
+            Cow::Borrowed("Rate"),
+            Cow::Borrowed("Phase"),
+            Cow::Borrowed("Tempo"),
+        ]
+    }
+
+    fn get_args(&self) -> Vec<String> {
+        vec![
+            self.shape.name().to_string(),
+            self.polarity.name().to_string(),
+        ]
+    }
+
+    fn state_len(&self, _sample_rate: u32) -> usize {
+        2
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        _phase: u64,
+        sample_rate: u32,
+    ) -> Sample {
+        let (rate, offset, tempo) = (
+            or_default(inputs[0], 0.0),
+            or_default(inputs[1], 0.0),
+            inputs[2],
+        );
+        let freq = Sample::stereo(synced(rate.l(), tempo.l()), synced(rate.r(), tempo.r()));
+
+        // the shifted phase may wrap into the next cycle
+        let shifted = state[0] + offset;
+        let cycle = state[1] + shifted.floor();
+        let t = shifted - shifted.floor();
+
+        let next = state[0] + freq / sample_rate as f64;
+        state[1] = state[1] + next.floor();
+        state[0] = next - next.floor();
+
+        let out = Sample::stereo(
+            self.shape.value(t.l(), cycle.l()),
+            self.shape.value(t.r(), cycle.r()),
+        );
+
+        match self.polarity {
+            Polarity::Unipolar => (out + 1.0) * 0.5,
+            Polarity::Bipolar => out,
+        }
+    }
+}
+
+/// Returns the frequency in Hz of `rate`, which is in cycles per beat if `tempo` is positive.
+/// Unconnected inputs read NaN, which isn't, so the rate stays in Hz.
+fn synced(rate: f64, tempo: f64) -> f64 {
+    if tempo.is_finite() && tempo > 0.0 {
+        rate * tempo / 60.0
+    } else {
+        rate
+    }
+}
@@ -0,0 +1,247 @@
+use std::{borrow::Cow, f64::consts};
+
+use serde::{Deserialize, Serialize};
+
+use crate::node::{Node, Opcode};
+use crate::Sample;
+
+/// Advances the phase accumulator in `state` by one sample of `freq`.
+///
+/// Returns the phase before advancing (in `0..1`) and the absolute phase increment.
+fn accumulate(state: &mut Sample, freq: Sample, sample_rate: u32) -> (Sample, Sample) {
+    let phase = *state;
+    let dt = freq / (sample_rate as f64);
+    let next = phase + dt;
+    *state = next - next.floor();
+
+    (phase, dt.abs())
+}
+
+/// Applies a waveform to both channels of `t`, which uses `dt` for anti-aliasing.
+fn per_channel(t: Sample, dt: Sample, f: impl Fn(f64, f64) -> f64) -> Sample {
+    Sample::stereo(f(t.l(), dt.l()), f(t.r(), dt.r()))
+}
+
+/// Polynomial band-limited step. Residual of a step of height 2 at `t = 0`.
+fn poly_blep(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let t = t / dt;
+        2.0 * t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// Polynomial band-limited ramp. Residual of a unit change in slope (per sample) at `t = 0`.
+fn poly_blamp(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let t = t / dt - 1.0;
+        -t * t * t / 3.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt + 1.0;
+        t * t * t / 3.0
+    } else {
+        0.0
+    }
+}
+
+fn saw(t: f64, dt: f64) -> f64 {
+    2.0 * t - 1.0 - poly_blep(t, dt)
+}
+
+fn pulse(t: f64, dt: f64, width: f64) -> f64 {
+    let naive = if t < width { 1.0 } else { -1.0 };
+    let falling = t - width;
+
+    naive + poly_blep(t, dt) - poly_blep(falling - falling.floor(), dt)
+}
+
+fn triangle(t: f64, dt: f64) -> f64 {
+    // the slope flips between -4 and 4 at each corner
+    let naive = 2.0 * (2.0 * t - 1.0).abs() - 1.0;
+    let rising = t + 0.5;
+
+    naive - 8.0 * dt * poly_blamp(t, dt) + 8.0 * dt * poly_blamp(rising - rising.floor(), dt)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Sine;
+
+#[typetag::serde]
+impl Node for Sine {
+    fn get_ident(&self) -> &str {
+        "Sine"
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &[Cow::Borrowed("Frequency")]
+    }
+
+    fn opcode(&self) -> Option<Opcode> {
+        Some(Opcode::Sine)
+    }
+
+    fn state_len(&self, _sample_rate: u32) -> usize {
+        1
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        _phase: u64,
+        sample_rate: u32,
+    ) -> Sample {
+        // accumulate the phase so that modulating the frequency doesn't jump
+        let (t, _) = accumulate(&mut state[0], inputs[0], sample_rate);
+
+        (t * consts::TAU).sin()
+    }
+}
+
+/// Band-limited sawtooth, rising from -1 to 1.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Saw;
+
+#[typetag::serde]
+impl Node for Saw {
+    fn get_ident(&self) -> &str {
+        "Saw"
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &[Cow::Borrowed("Frequency")]
+    }
+
+    fn opcode(&self) -> Option<Opcode> {
+        Some(Opcode::Saw)
+    }
+
+    fn state_len(&self, _sample_rate: u32) -> usize {
+        1
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        _phase: u64,
+        sample_rate: u32,
+    ) -> Sample {
+        let (t, dt) = accumulate(&mut state[0], inputs[0], sample_rate);
+
+        per_channel(t, dt, saw)
+    }
+}
+
+/// Band-limited square, high for the first half of each cycle.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Square;
+
+#[typetag::serde]
+impl Node for Square {
+    fn get_ident(&self) -> &str {
+        "Square"
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &[Cow::Borrowed("Frequency")]
+    }
+
+    fn opcode(&self) -> Option<Opcode> {
+        Some(Opcode::Square)
+    }
+
+    fn state_len(&self, _sample_rate: u32) -> usize {
+        1
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        _phase: u64,
+        sample_rate: u32,
+    ) -> Sample {
+        let (t, dt) = accumulate(&mut state[0], inputs[0], sample_rate);
+
+        per_channel(t, dt, |t, dt| pulse(t, dt, 0.5))
+    }
+}
+
+/// Band-limited triangle, falling from 1 to -1 over the first half of each cycle.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Triangle;
+
+#[typetag::serde]
+impl Node for Triangle {
+    fn get_ident(&self) -> &str {
+        "Triangle"
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &[Cow::Borrowed("Frequency")]
+    }
+
+    fn opcode(&self) -> Option<Opcode> {
+        Some(Opcode::Triangle)
+    }
+
+    fn state_len(&self, _sample_rate: u32) -> usize {
+        1
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        _phase: u64,
+        sample_rate: u32,
+    ) -> Sample {
+        let (t, dt) = accumulate(&mut state[0], inputs[0], sample_rate);
+
+        per_channel(t, dt, triangle)
+    }
+}
+
+/// Band-limited pulse, high for the fraction of each cycle set by `Width` (0 to 1).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Pulse;
+
+#[typetag::serde]
+impl Node for Pulse {
+    fn get_ident(&self) -> &str {
+        "Pulse"
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &[Cow::Borrowed("Frequency"), Cow::Borrowed("Width")]
+    }
+
+    fn opcode(&self) -> Option<Opcode> {
+        Some(Opcode::Pulse)
+    }
+
+    fn state_len(&self, _sample_rate: u32) -> usize {
+        1
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        state: &mut [Sample],
+        _phase: u64,
+        sample_rate: u32,
+    ) -> Sample {
+        let (t, dt) = accumulate(&mut state[0], inputs[0], sample_rate);
+        let width = inputs[1].clamp(0.0, 1.0);
+
+        Sample::stereo(
+            pulse(t.l(), dt.l(), width.l()),
+            pulse(t.r(), dt.r(), width.r()),
+        )
+    }
+}
@@ -0,0 +1,170 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::node::Node;
+use crate::Sample;
+
+/// A value that's fed into the graph from outside of it, such as the note played by a voice.
+/// See [ControlGraph::set_source](crate::control::ControlGraph::set_source).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Source {
+    /// The MIDI note number of the voice's note.
+    NoteNumber,
+    /// The frequency of the voice's note, in Hz.
+    NoteFrequency,
+    /// The velocity of the voice's note, from 0 to 1.
+    Velocity,
+    /// 1 while the voice's note is held, otherwise 0.
+    Gate,
+    /// The polyphonic pressure of the voice's note, or the channel pressure, from 0 to 1.
+    Aftertouch,
+    /// The pitch bend wheel, from -1 to 1.
+    PitchBend,
+    /// The value of a MIDI CC number, from 0 to 1.
+    Cc(u8),
+    /// The value of a macro parameter, mapped into the range of its [Macro].
+    Param(u8),
+    /// The host's tempo, in beats per minute.
+    Tempo,
+}
+
+/// The number of macro parameters that the host can automate.
+pub const MACRO_COUNT: usize = 16;
+
+/// Describes a macro parameter, whose [ParamSource] nodes output its value mapped from 0..1 into
+/// `min..max`. Saved along with the control graph.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Macro {
+    pub name: String,
+    pub min: f64,
+    pub max: f64,
+    /// Displayed after the value, such as `" Hz"`.
+    pub unit: String,
+}
+
+impl Default for Macro {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            min: 0.0,
+            max: 1.0,
+            unit: String::new(),
+        }
+    }
+}
+
+impl Macro {
+    /// Maps a `normalized` value from 0 to 1 into the macro's range.
+    pub fn map(&self, normalized: f64) -> f64 {
+        self.min + (self.max - self.min) * normalized
+    }
+
+    /// Maps a `value` in the macro's range back to 0..1.
+    pub fn unmap(&self, value: f64) -> f64 {
+        if self.max == self.min {
+            return 0.0;
+        }
+
+        ((value - self.min) / (self.max - self.min)).clamp(0.0, 1.0)
+    }
+}
+
+/// Declares a node without inputs that outputs the last value set for its [Source].
+///
+/// `Node = "Ident";` declares a unit struct for `Source::Node`, and
+/// `Node(u8) = "Ident" => Source::Variant;` declares a struct that holds the variant's field,
+/// which is also the node's argument in the [text patch format](crate::patch).
+macro_rules! source_nodes {
+    () => {};
+    (@impl $node: ident, $ident: literal, |$this: ident| $source: expr, $args: expr) => {
+        #[typetag::serde]
+        impl Node for $node {
+            fn get_ident(&self) -> &str {
+                $ident
+            }
+
+            fn get_input_labels(&self) -> &[Cow<'static, str>] {
+                &[]
+            }
+
+            fn get_args(&self) -> Vec<String> {
+                let $this = self;
+                $args
+            }
+
+            fn get_source(&self) -> Option<Source> {
+                let $this = self;
+                Some($source)
+            }
+
+            fn state_len(&self, _sample_rate: u32) -> usize {
+                1
+            }
+
+            /// The value is set from outside of the graph, so it's kept.
+            fn reset_state(&self, _state: &mut [Sample]) {}
+
+            fn process(
+                &self,
+                _inputs: &[Sample],
+                state: &mut [Sample],
+                _phase: u64,
+                _sample_rate: u32,
+            ) -> Sample {
+                state[0]
+            }
+
+            fn process_block(
+                &self,
+                _inputs: &[Sample],
+                state: &mut [Sample],
+                outputs: &mut [Sample],
+                _phase: u64,
+                _sample_rate: u32,
+            ) {
+                outputs.fill(state[0]);
+            }
+        }
+    };
+    ($(#[$attr: meta])* $node: ident = $ident: literal; $($rest: tt)*) => {
+        $(#[$attr])*
+        #[derive(Debug, Serialize, Deserialize)]
+        pub struct $node;
+
+        source_nodes!(@impl $node, $ident, |_node| Source::$node, vec![]);
+        source_nodes!($($rest)*);
+    };
+    (
+        $(#[$attr: meta])* $node: ident($field: ty) = $ident: literal => $source: path;
+        $($rest: tt)*
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Serialize, Deserialize)]
+        pub struct $node(pub $field);
+
+        source_nodes!(@impl $node, $ident, |node| $source(node.0), vec![node.0.to_string()]);
+        source_nodes!($($rest)*);
+    };
+}
+
+source_nodes! {
+    /// Outputs [Source::NoteNumber].
+    NoteNumber = "NoteNumber";
+    /// Outputs [Source::NoteFrequency].
+    NoteFrequency = "NoteFrequency";
+    /// Outputs [Source::Velocity].
+    Velocity = "Velocity";
+    /// Outputs [Source::Gate].
+    Gate = "Gate";
+    /// Outputs [Source::Aftertouch].
+    Aftertouch = "Aftertouch";
+    /// Outputs [Source::PitchBend].
+    PitchBend = "PitchBend";
+    /// Outputs [Source::Tempo].
+    Tempo = "Tempo";
+    /// Outputs [Source::Cc] for the CC number that it holds.
+    Cc(u8) = "CC" => Source::Cc;
+    /// Outputs [Source::Param] for the macro that it holds.
+    ParamSource(u8) = "ParamSource" => Source::Param;
+}
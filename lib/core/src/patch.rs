@@ -0,0 +1,802 @@
+//! A text format for control graphs. Unlike the postcard data written by
+//! [ControlGraph::save], patches can be read, reviewed and diffed.
+//!
+//! Each line is a statement, and `#` begins a comment:
+//!
+//! ```text
+//! # containers are numbered in the order that they're declared
+//! container 0 Subtract
+//! container 1 Inner in 0
+//!
+//! # nodes are an id, an ident, the node's arguments, then the innermost container it's in
+//! node 1 Constant 440
+//! node 2 Sine
+//! node 3 Constant 0.25 0.75
+//! node 4 ContainerInput LHS in 0
+//!
+//! # edges connect `node:output` to `node:input`, where ports are numbers or labels
+//! edge 1:0 -> 2:Frequency
+//! edge 2:0 -> aout
+//!
+//! # feeds output 0 of node 2 back through the `Feedback` node 5
+//! node 5 Feedback 1
+//! feedback 2:0 -> 5
+//!
+//! # macros are a number, a name, a range, then an optional unit
+//! macro 0 Cutoff 20 20000 " Hz"
+//! node 6 ParamSource 0
+//! ```
+//!
+//! Arguments containing whitespace, `"` or `#` are written in double quotes, where `\"` and `\\`
+//! escape a quote and a backslash.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Write};
+
+use petgraph::graph::NodeIndex;
+
+use crate::control::ControlGraph;
+use crate::error::{EnvelopeError, GraphError};
+use crate::node::*;
+use crate::Sample;
+
+/// An error in a text patch, at a 1-based `line` and `column`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// Writes the control graph as a text patch.
+///
+/// Only the nodes that [parse] knows how to construct can be written. If the graph has any
+/// others, returns the error that parsing the patch back would.
+pub fn write(cg: &ControlGraph) -> Result<String, PatchError> {
+    let mut out = String::new();
+    let aout = cg.get_aout_node();
+
+    for i in 0..cg.get_container_count() {
+        write!(out, "container {i} {}", quote(cg.get_container_ident(i))).unwrap();
+        if let Some(parent) = cg.get_container_parent(i) {
+            write!(out, " in {parent}").unwrap();
+        }
+        out.push('\n');
+    }
+
+    if cg.get_container_count() > 0 {
+        out.push('\n');
+    }
+
+    for node_index in cg.get_node_indexes().filter(|&n| n != aout) {
+        let node = cg.get_node(node_index);
+
+        write!(out, "node {} {}", node_index.index(), node.get_ident()).unwrap();
+        for arg in node.get_args() {
+            write!(out, " {}", quote(&arg)).unwrap();
+        }
+        if let Some(container) = innermost_container(cg, node_index) {
+            write!(out, " in {container}").unwrap();
+        }
+        out.push('\n');
+    }
+
+    out.push('\n');
+
+    for edge in cg.dag.edge_indices() {
+        let (src, dest) = cg.dag.edge_endpoints(edge).unwrap();
+        let weight = cg.dag[edge];
+
+        write!(out, "edge {}:{} -> ", src.index(), weight.src_port).unwrap();
+        if dest == aout {
+            out.push_str("aout\n");
+        } else {
+            writeln!(out, "{}:{}", dest.index(), weight.dest_port).unwrap();
+        }
+    }
+
+    for f in cg.get_feedback_edges() {
+        writeln!(
+            out,
+            "feedback {}:{} -> {}",
+            f.src.index(),
+            f.src_port,
+            f.tap.index()
+        )
+        .unwrap();
+    }
+
+    let mut macros = (0..MACRO_COUNT)
+        .map(|n| (n, cg.get_macro(n)))
+        .filter(|(_, m)| **m != Macro::default())
+        .peekable();
+
+    if macros.peek().is_some() {
+        out.push('\n');
+    }
+
+    for (n, m) in macros {
+        write!(out, "macro {n} {} {} {}", quote(&m.name), m.min, m.max).unwrap();
+        if !m.unit.is_empty() {
+            write!(out, " {}", quote(&m.unit)).unwrap();
+        }
+        out.push('\n');
+    }
+
+    // checks the whole round trip, rather than each node's ident
+    parse(cg.get_sample_rate(), &out)?;
+
+    Ok(out)
+}
+
+/// Parses a text patch into a control graph with its `sample_rate` set.
+pub fn parse(sample_rate: u32, src: &str) -> Result<ControlGraph, PatchError> {
+    let mut parser = Parser {
+        cg: ControlGraph::new(sample_rate),
+        nodes: HashMap::new(),
+    };
+
+    for (i, line) in src.lines().enumerate() {
+        let line = Line::tokenize(i + 1, line)?;
+
+        if !line.tokens.is_empty() {
+            parser.statement(&line)?;
+        }
+    }
+
+    Ok(parser.cg)
+}
+
+/// Returns the deepest container that `node` is a member of.
+fn innermost_container(cg: &ControlGraph, node: NodeIndex) -> Option<usize> {
+    let depth = |mut i| {
+        let mut depth = 0;
+        while let Some(parent) = cg.get_container_parent(i) {
+            depth += 1;
+            i = parent;
+        }
+
+        depth
+    };
+
+    (0..cg.get_container_count())
+        .filter(|&i| cg.get_container_member_indexes(i).any(|&n| n == node))
+        .max_by_key(|&i| depth(i))
+}
+
+/// Quotes `text` if it wouldn't be read back as a single plain token.
+fn quote(text: &str) -> String {
+    let plain = !text.is_empty()
+        && text != "in"
+        && !text
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '#' || c == '\\');
+
+    if plain {
+        text.into()
+    } else {
+        format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+struct Token {
+    text: String,
+    column: usize,
+    quoted: bool,
+}
+
+impl Token {
+    /// Returns whether this is the unquoted keyword `keyword`.
+    fn is(&self, keyword: &str) -> bool {
+        !self.quoted && self.text == keyword
+    }
+}
+
+struct Line {
+    number: usize,
+    tokens: Vec<Token>,
+    /// The column just past the end of the line, where missing tokens are reported.
+    end: usize,
+}
+
+impl Line {
+    fn tokenize(number: usize, line: &str) -> Result<Self, PatchError> {
+        let mut tokens = vec![];
+        let mut chars = line.chars().enumerate().peekable();
+        let err = |column: usize, message: &str| PatchError {
+            line: number,
+            column,
+            message: message.into(),
+        };
+
+        while let Some(&(i, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '#' {
+                break;
+            } else if c == '"' {
+                chars.next();
+
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c @ ('"' | '\\'))) => text.push(c),
+                            Some((j, _)) => return Err(err(j + 1, "expected `\"` or `\\`")),
+                            None => return Err(err(i + 1, "unterminated string")),
+                        },
+                        Some((_, c)) => text.push(c),
+                        None => return Err(err(i + 1, "unterminated string")),
+                    }
+                }
+
+                tokens.push(Token {
+                    text,
+                    column: i + 1,
+                    quoted: true,
+                });
+            } else {
+                let mut text = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || c == '"' || c == '#' {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+
+                tokens.push(Token {
+                    text,
+                    column: i + 1,
+                    quoted: false,
+                });
+            }
+        }
+
+        Ok(Self {
+            number,
+            tokens,
+            end: line.chars().count() + 1,
+        })
+    }
+
+    fn err(&self, column: usize, message: impl Into<String>) -> PatchError {
+        PatchError {
+            line: self.number,
+            column,
+            message: message.into(),
+        }
+    }
+
+    /// Returns token `i`, which is described by `what` if it's missing.
+    fn expect(&self, i: usize, what: &str) -> Result<&Token, PatchError> {
+        self.tokens
+            .get(i)
+            .ok_or_else(|| self.err(self.end, format!("expected {what}")))
+    }
+
+    /// Fails if there are any tokens from `i` onwards.
+    fn expect_end(&self, i: usize) -> Result<(), PatchError> {
+        match self.tokens.get(i) {
+            Some(t) => Err(self.err(t.column, format!("unexpected `{}`", t.text))),
+            None => Ok(()),
+        }
+    }
+
+    fn parse<T: std::str::FromStr>(&self, token: &Token, what: &str) -> Result<T, PatchError> {
+        token.text.parse().map_err(|_| {
+            self.err(
+                token.column,
+                format!("expected {what}, found `{}`", token.text),
+            )
+        })
+    }
+}
+
+struct Parser {
+    cg: ControlGraph,
+    /// The node that each id in the patch was inserted as.
+    nodes: HashMap<usize, NodeIndex>,
+}
+
+impl Parser {
+    fn statement(&mut self, line: &Line) -> Result<(), PatchError> {
+        let keyword = &line.tokens[0];
+
+        match keyword.text.as_str() {
+            "container" if !keyword.quoted => self.container(line),
+            "node" if !keyword.quoted => self.node(line),
+            "edge" if !keyword.quoted => self.edge(line),
+            "feedback" if !keyword.quoted => self.feedback(line),
+            "macro" if !keyword.quoted => self.macro_desc(line),
+            _ => Err(line.err(
+                keyword.column,
+                format!(
+                    "expected `container`, `node`, `edge`, `feedback` or `macro`, found `{}`",
+                    keyword.text
+                ),
+            )),
+        }
+    }
+
+    /// `container <id> <ident> [in <container>]`
+    fn container(&mut self, line: &Line) -> Result<(), PatchError> {
+        let id_token = line.expect(1, "a container id")?;
+        let id: usize = line.parse(id_token, "a container id")?;
+        let expected = self.cg.get_container_count();
+
+        if id != expected {
+            return Err(line.err(
+                id_token.column,
+                format!("expected container {expected}, since containers are numbered in order"),
+            ));
+        }
+
+        let ident = line.expect(2, "a container ident")?;
+        let parent = self.container_membership(line, 3)?;
+
+        self.cg.add_container(&ident.text, parent);
+
+        Ok(())
+    }
+
+    /// `node <id> <ident> [args...] [in <container>]`
+    fn node(&mut self, line: &Line) -> Result<(), PatchError> {
+        let id_token = line.expect(1, "a node id")?;
+        let id: usize = line.parse(id_token, "a node id")?;
+
+        if self.nodes.contains_key(&id) {
+            return Err(line.err(id_token.column, format!("node {id} is already declared")));
+        }
+
+        let ident = line.expect(2, "a node ident")?;
+        let args_end = line.tokens[3..]
+            .iter()
+            .position(|t| t.is("in"))
+            .map_or(line.tokens.len(), |i| i + 3);
+
+        // missing arguments are reported where the arguments end
+        let end = line.tokens.get(args_end).map_or(line.end, |t| t.column);
+        let node = construct(line, ident, &line.tokens[3..args_end], end)?;
+        let container = self.container_membership(line, args_end)?;
+
+        let node_index = self.cg.insert_boxed(node);
+        if let Some(container) = container {
+            self.cg.add_container_member(container, node_index);
+        }
+
+        self.nodes.insert(id, node_index);
+
+        Ok(())
+    }
+
+    /// `edge <node>:<output> -> <node>:<input>`, or `edge <node>:<output> -> aout`
+    fn edge(&mut self, line: &Line) -> Result<(), PatchError> {
+        let src_token = line.expect(1, "a source port")?;
+        let (src, src_port) = self.port(line, src_token, false)?;
+        self.arrow(line)?;
+
+        let dest_token = line.expect(3, "a destination port or `aout`")?;
+        let (dest, dest_port) = if dest_token.is("aout") {
+            (self.cg.get_aout_node(), 0)
+        } else {
+            self.port(line, dest_token, true)?
+        };
+
+        line.expect_end(4)?;
+
+        self.cg
+            .try_connect_ports(src, src_port, dest, dest_port)
+            .map_err(|e| self.graph_err(line, src_token.column, e))?;
+
+        Ok(())
+    }
+
+    /// `feedback <node>:<output> -> <feedback node>`
+    fn feedback(&mut self, line: &Line) -> Result<(), PatchError> {
+        let src_token = line.expect(1, "a source port")?;
+        let (src, src_port) = self.port(line, src_token, false)?;
+        self.arrow(line)?;
+
+        let tap_token = line.expect(3, "a `Feedback` node")?;
+        let tap = self.node_id(line, tap_token, &tap_token.text)?;
+
+        if self.cg.get_node(tap).get_ident() != "Feedback" {
+            return Err(line.err(
+                tap_token.column,
+                format!("node {} isn't a `Feedback` node", tap_token.text),
+            ));
+        }
+
+        line.expect_end(4)?;
+
+        self.cg
+            .try_connect_feedback_tap(src, src_port, tap)
+            .map_err(|e| self.graph_err(line, src_token.column, e))
+    }
+
+    /// `macro <n> <name> <min> <max> [unit]`
+    fn macro_desc(&mut self, line: &Line) -> Result<(), PatchError> {
+        let n_token = line.expect(1, "a macro number")?;
+        let n: usize = line.parse(n_token, "a macro number")?;
+
+        if n >= MACRO_COUNT {
+            return Err(line.err(
+                n_token.column,
+                format!("macros go from 0 to {}", MACRO_COUNT - 1),
+            ));
+        }
+
+        let name = line.expect(2, "a macro name")?.text.clone();
+        let min = line.parse(line.expect(3, "a minimum")?, "a number")?;
+        let max = line.parse(line.expect(4, "a maximum")?, "a number")?;
+        let unit = line.tokens.get(5).map_or(String::new(), |t| t.text.clone());
+        line.expect_end(6)?;
+
+        self.cg.set_macro(
+            n,
+            Macro {
+                name,
+                min,
+                max,
+                unit,
+            },
+        );
+
+        Ok(())
+    }
+
+    fn arrow(&self, line: &Line) -> Result<(), PatchError> {
+        let arrow = line.expect(2, "`->`")?;
+
+        if arrow.is("->") {
+            Ok(())
+        } else {
+            Err(line.err(
+                arrow.column,
+                format!("expected `->`, found `{}`", arrow.text),
+            ))
+        }
+    }
+
+    /// Parses the optional `in <container>` beginning at token `i`, which ends the line.
+    fn container_membership(&self, line: &Line, i: usize) -> Result<Option<usize>, PatchError> {
+        let Some(keyword) = line.tokens.get(i) else {
+            return Ok(None);
+        };
+
+        if !keyword.is("in") {
+            return Err(line.err(
+                keyword.column,
+                format!("expected `in`, found `{}`", keyword.text),
+            ));
+        }
+
+        let container_token = line.expect(i + 1, "a container id")?;
+        let container: usize = line.parse(container_token, "a container id")?;
+
+        if container >= self.cg.get_container_count() {
+            return Err(line.err(
+                container_token.column,
+                format!("container {container} isn't declared"),
+            ));
+        }
+
+        line.expect_end(i + 2)?;
+
+        Ok(Some(container))
+    }
+
+    /// Looks up the node declared as `id`.
+    fn node_id(&self, line: &Line, token: &Token, id: &str) -> Result<NodeIndex, PatchError> {
+        let parsed: usize = id
+            .parse()
+            .map_err(|_| line.err(token.column, format!("expected a node id, found `{id}`")))?;
+
+        self.nodes
+            .get(&parsed)
+            .copied()
+            .ok_or_else(|| line.err(token.column, format!("node {parsed} isn't declared")))
+    }
+
+    /// Parses `<node>:<port>`, where the port is either a number or a label of the node's inputs
+    /// (if `input`) or outputs.
+    fn port(
+        &self,
+        line: &Line,
+        token: &Token,
+        input: bool,
+    ) -> Result<(NodeIndex, usize), PatchError> {
+        let Some((id, port)) = token.text.split_once(':') else {
+            return Err(line.err(
+                token.column,
+                format!("expected `node:port`, found `{}`", token.text),
+            ));
+        };
+
+        let node = self.node_id(line, token, id)?;
+        let port_column = token.column + id.chars().count() + 1;
+
+        if let Ok(port) = port.parse() {
+            return Ok((node, port));
+        }
+
+        let port = if input {
+            self.cg.try_get_input_port(node, port)
+        } else {
+            self.cg.try_get_output_port(node, port)
+        };
+
+        port.map(|p| (node, p))
+            .map_err(|e| self.graph_err(line, port_column, e))
+    }
+
+    /// Returns the id that `node` was declared as in the patch, or `aout`.
+    fn node_name(&self, node: NodeIndex) -> String {
+        if node == self.cg.get_aout_node() {
+            return "aout".into();
+        }
+
+        self.nodes
+            .iter()
+            .find(|(_, &n)| n == node)
+            .map_or_else(|| node.index().to_string(), |(id, _)| id.to_string())
+    }
+
+    /// Reports `e` at `column`, naming nodes by their ids in the patch rather than the indexes
+    /// they were inserted at.
+    fn graph_err(&self, line: &Line, column: usize, e: GraphError) -> PatchError {
+        let name = |node| self.node_name(node);
+
+        let message = match e {
+            GraphError::WouldCycle { src, dest } => format!(
+                "adding edge {} -> {} would cause a cycle",
+                name(src),
+                name(dest)
+            ),
+            GraphError::NoSuchNode(node) => format!("node {} doesn't exist", name(node)),
+            GraphError::NoSuchPort { node, label, valid } => format!(
+                "node {} has no port named \"{label}\" (expected one of: {})",
+                name(node),
+                valid.join(", ")
+            ),
+            GraphError::PortOutOfRange { node, port, len } => format!(
+                "port {port} is out of range for node {}, which has {len} ports",
+                name(node)
+            ),
+            GraphError::OutputOutOfRange { node, port, len } => format!(
+                "output {port} is out of range for node {}, which has {len} outputs",
+                name(node)
+            ),
+            GraphError::PortOccupied { node, port } => {
+                format!("port {port} of node {} is already connected", name(node))
+            }
+            GraphError::FeedbackWithoutDelay { src, dest } => format!(
+                "feedback from {} to {} needs a delay of at least one sample",
+                name(src),
+                name(dest)
+            ),
+            e @ (GraphError::NoSuchContainerPort { .. } | GraphError::AoutUnconnected) => {
+                e.to_string()
+            }
+        };
+
+        line.err(column, message)
+    }
+}
+
+/// Returns the item of `all` whose `name` is `token`.
+fn named<T: Copy>(
+    line: &Line,
+    token: &Token,
+    all: &[T],
+    name: fn(&T) -> &'static str,
+) -> Result<T, PatchError> {
+    all.iter()
+        .find(|item| name(item) == token.text)
+        .copied()
+        .ok_or_else(|| {
+            let names = all.iter().map(name).collect::<Vec<_>>().join(", ");
+            line.err(
+                token.column,
+                format!("expected one of {names}, found `{}`", token.text),
+            )
+        })
+}
+
+/// Parses the longest time in seconds that a delay can be set to.
+fn max_time(line: &Line, token: &Token) -> Result<f64, PatchError> {
+    let max_time: f64 = line.parse(token, "a time in seconds")?;
+
+    if !(max_time >= 0.0 && max_time.is_finite()) {
+        return Err(line.err(token.column, "delay times must be finite and at least 0"));
+    }
+
+    Ok(max_time)
+}
+
+/// Constructs the node labeled `ident` from its arguments, which end at column `end`.
+fn construct(
+    line: &Line,
+    ident: &Token,
+    args: &[Token],
+    end: usize,
+) -> Result<Box<dyn Node>, PatchError> {
+    let arg = |i: usize, what: &str| {
+        args.get(i)
+            .ok_or_else(|| line.err(end, format!("expected {what}")))
+    };
+    let args_end = |i: usize| match args.get(i) {
+        Some(t) => Err(line.err(t.column, format!("unexpected `{}`", t.text))),
+        None => Ok(()),
+    };
+    let no_args = |node: Box<dyn Node>| args_end(0).map(|_| node);
+
+    match ident.text.as_str() {
+        "Add" => no_args(Box::new(Add)),
+        "Multiply" => no_args(Box::new(Mul)),
+        "Inverse" => no_args(Box::new(Inv)),
+        "Split" => no_args(Box::new(Split)),
+        "Sine" => no_args(Box::new(Sine)),
+        "Saw" => no_args(Box::new(Saw)),
+        "Square" => no_args(Box::new(Square)),
+        "Triangle" => no_args(Box::new(Triangle)),
+        "Pulse" => no_args(Box::new(Pulse)),
+        "Svf" => no_args(Box::new(Svf)),
+        "Ladder" => no_args(Box::new(Ladder)),
+        "Biquad" => {
+            let mode = named(
+                line,
+                arg(0, "a filter mode")?,
+                &BiquadMode::ALL,
+                BiquadMode::name,
+            )?;
+            args_end(1)?;
+
+            Ok(Box::new(Biquad(mode)))
+        }
+        "Adsr" => {
+            let curve = named(line, arg(0, "a curve")?, &Curve::ALL, Curve::name)?;
+            let trigger = named(
+                line,
+                arg(1, "a trigger mode")?,
+                &Trigger::ALL,
+                Trigger::name,
+            )?;
+            args_end(2)?;
+
+            Ok(Box::new(Adsr { curve, trigger }))
+        }
+        "MultiStageEnvelope" => {
+            let stages_token = arg(0, "a number of stages")?;
+            let stages = line.parse(stages_token, "a number of stages")?;
+            let sustain_token = arg(1, "a sustain stage")?;
+            let sustain = match sustain_token.text.as_str() {
+                "none" => None,
+                _ => Some(line.parse(sustain_token, "a sustain stage or `none`")?),
+            };
+            let curve = named(line, arg(2, "a curve")?, &Curve::ALL, Curve::name)?;
+            let trigger = named(
+                line,
+                arg(3, "a trigger mode")?,
+                &Trigger::ALL,
+                Trigger::name,
+            )?;
+            args_end(4)?;
+
+            match MultiStageEnvelope::new(stages, sustain, curve, trigger) {
+                Ok(envelope) => Ok(Box::new(envelope)),
+                Err(e @ EnvelopeError::NoStages) => {
+                    Err(line.err(stages_token.column, e.to_string()))
+                }
+                Err(e @ EnvelopeError::SustainPastEnd { .. }) => {
+                    Err(line.err(sustain_token.column, e.to_string()))
+                }
+            }
+        }
+        "Lfo" => {
+            let shape = named(line, arg(0, "a shape")?, &LfoShape::ALL, LfoShape::name)?;
+            let polarity = named(line, arg(1, "a polarity")?, &Polarity::ALL, Polarity::name)?;
+            args_end(2)?;
+
+            Ok(Box::new(Lfo { shape, polarity }))
+        }
+        "Delay" => {
+            let max_time = max_time(line, arg(0, "a maximum time")?)?;
+            args_end(1)?;
+
+            Ok(Box::new(Delay { max_time }))
+        }
+        "FractionalDelay" => {
+            let max_time = max_time(line, arg(0, "a maximum time")?)?;
+            let interpolation = named(
+                line,
+                arg(1, "an interpolation")?,
+                &Interpolation::ALL,
+                Interpolation::name,
+            )?;
+            args_end(2)?;
+
+            Ok(Box::new(FractionalDelay {
+                max_time,
+                interpolation,
+            }))
+        }
+        "NoteNumber" => no_args(Box::new(NoteNumber)),
+        "Velocity" => no_args(Box::new(Velocity)),
+        "Gate" => no_args(Box::new(Gate)),
+        "NoteFrequency" => no_args(Box::new(NoteFrequency)),
+        "Aftertouch" => no_args(Box::new(Aftertouch)),
+        "PitchBend" => no_args(Box::new(PitchBend)),
+        "Tempo" => no_args(Box::new(Tempo)),
+        "CC" => {
+            let cc_token = arg(0, "a CC number")?;
+            let cc = line.parse(cc_token, "a CC number from 0 to 127")?;
+            args_end(1)?;
+
+            if cc > 127 {
+                return Err(line.err(cc_token.column, "CC numbers go from 0 to 127"));
+            }
+
+            Ok(Box::new(Cc(cc)))
+        }
+        "ParamSource" => {
+            let n_token = arg(0, "a macro number")?;
+            let n: u8 = line.parse(n_token, "a macro number")?;
+            args_end(1)?;
+
+            if n as usize >= MACRO_COUNT {
+                return Err(line.err(
+                    n_token.column,
+                    format!("macros go from 0 to {}", MACRO_COUNT - 1),
+                ));
+            }
+
+            Ok(Box::new(ParamSource(n)))
+        }
+        "Constant" => {
+            let l = line.parse(arg(0, "a value")?, "a number")?;
+            let r = match args.get(1) {
+                Some(r) => line.parse(r, "a number")?,
+                None => l,
+            };
+            args_end(2)?;
+
+            Ok(Box::new(Const(Sample::stereo(l, r))))
+        }
+        "Feedback" => {
+            let delay_token = arg(0, "a delay")?;
+            let delay = line.parse(delay_token, "a delay in samples")?;
+            args_end(1)?;
+
+            if delay == 0 {
+                return Err(line.err(
+                    delay_token.column,
+                    "feedback needs a delay of at least one sample",
+                ));
+            }
+
+            Ok(Box::new(Feedback { delay }))
+        }
+        "ContainerInput" | "ContainerOutput" => {
+            let label = arg(0, "a label")?.text.clone();
+            args_end(1)?;
+
+            Ok(if ident.text == "ContainerInput" {
+                Box::new(ContainerInput([label.into()]))
+            } else {
+                Box::new(ContainerOutput([label.into()]))
+            })
+        }
+        _ => Err(line.err(ident.column, format!("unknown node `{}`", ident.text))),
+    }
+}
@@ -0,0 +1,164 @@
+//! A pool of worker threads that a real-time thread splits work with, without blocking on them,
+//! allocating or making system calls.
+
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How many times an idle worker checks for a job before it starts sleeping between checks.
+const SPINS: u32 = 1 << 14;
+
+/// How long a sleeping worker waits between checks. Workers are never woken up, so that
+/// starting a job doesn't make a system call; the thread that starts it works on it too.
+const SLEEP: Duration = Duration::from_micros(250);
+
+/// The tasks of a job, called with the index of each task.
+type Task<'a> = dyn Fn(usize) + Sync + 'a;
+
+/// Worker threads that help run the tasks of a job started with [ThreadPool::run].
+///
+/// The thread that calls [ThreadPool::run] runs tasks as well, so a job always finishes even if
+/// no worker picks it up, and only ever waits for tasks that a worker has already started.
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    /// The running job, or null. Points to a `&Task` on the stack of [ThreadPool::run].
+    job: AtomicPtr<&'static Task<'static>>,
+    /// The number of tasks in the running job.
+    tasks: AtomicUsize,
+    /// The index of the next task to claim.
+    next: AtomicUsize,
+    /// The number of workers that may be holding onto `job`.
+    active: AtomicUsize,
+    /// Whether a thread is in [ThreadPool::run].
+    running: AtomicBool,
+    shutdown: AtomicBool,
+}
+
+impl Shared {
+    /// Claims and runs tasks of the running job until they've all been claimed.
+    fn work(&self, job: &Task) {
+        let tasks = self.tasks.load(Ordering::SeqCst);
+
+        loop {
+            let i = self.next.fetch_add(1, Ordering::SeqCst);
+            if i >= tasks {
+                break;
+            }
+
+            job(i);
+        }
+    }
+}
+
+impl Default for ThreadPool {
+    /// Spawns a worker for each core but the one of the thread that calls [ThreadPool::run].
+    fn default() -> Self {
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        Self::new(cores - 1)
+    }
+}
+
+impl ThreadPool {
+    /// Spawns `workers` threads. A pool without workers runs every task on the calling thread.
+    pub fn new(workers: usize) -> Self {
+        let shared = Arc::new(Shared::default());
+
+        let workers = (0..workers)
+            .map(|i| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("dagrid-worker-{i}"))
+                    .spawn(move || worker(&shared))
+                    .expect("failed to spawn a worker thread")
+            })
+            .collect();
+
+        Self { shared, workers }
+    }
+
+    /// Returns the number of worker threads, not counting the thread that calls
+    /// [ThreadPool::run].
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Calls `f` once with each index in `0..tasks`, spread over the calling thread and any idle
+    /// workers, and returns once every call has returned. Tasks may run in any order.
+    ///
+    /// Doesn't block on workers that haven't started a task, so it's safe to call from a
+    /// real-time thread. If another thread is already running a job, or `f` calls this, every
+    /// task runs on the calling thread.
+    pub fn run(&self, tasks: usize, f: &(dyn Fn(usize) + Sync)) {
+        let shared = &*self.shared;
+
+        if shared.running.swap(true, Ordering::SeqCst) {
+            (0..tasks).for_each(f);
+            return;
+        }
+
+        // no worker holds onto the last job, since `run` waits for them before returning
+        shared.tasks.store(tasks, Ordering::SeqCst);
+        shared.next.store(0, Ordering::SeqCst);
+
+        // SAFETY: `job` is cleared, and every worker that loaded it is waited for, before `f`
+        // goes out of scope at the end of this function
+        let job: &'static Task<'static> = unsafe { std::mem::transmute(f) };
+        let job_ptr = &job as *const &Task as *mut &Task;
+        shared.job.store(job_ptr, Ordering::SeqCst);
+
+        shared.work(f);
+
+        shared.job.store(std::ptr::null_mut(), Ordering::SeqCst);
+        while shared.active.load(Ordering::SeqCst) > 0 {
+            std::hint::spin_loop();
+        }
+
+        shared.running.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+
+        for worker in self.workers.drain(..) {
+            worker.thread().unpark();
+            let _ = worker.join();
+        }
+    }
+}
+
+fn worker(shared: &Shared) {
+    let mut idle = 0;
+
+    while !shared.shutdown.load(Ordering::SeqCst) {
+        if shared.job.load(Ordering::SeqCst).is_null() {
+            if idle < SPINS {
+                idle += 1;
+                std::hint::spin_loop();
+            } else {
+                thread::park_timeout(SLEEP);
+            }
+
+            continue;
+        }
+
+        // the job is only used if it's still running once this worker is counted as active, in
+        // which case `run` waits for it to finish
+        shared.active.fetch_add(1, Ordering::SeqCst);
+        let job = shared.job.load(Ordering::SeqCst);
+        if !job.is_null() {
+            // SAFETY: see above
+            shared.work(unsafe { *job });
+        }
+        shared.active.fetch_sub(1, Ordering::SeqCst);
+
+        idle = 0;
+    }
+}
@@ -9,6 +9,21 @@ pub fn preset<F: Fn(&mut ControlGraph)>(sample_rate: u32, f: F) -> ControlGraph
     cg
 }
 
+/// Builds a graph into an empty control graph.
+pub type Preset = fn(&mut ControlGraph);
+
+/// Every preset, along with the name it's looked up by in [named].
+pub const PRESETS: &[(&str, Preset)] = &[
+    ("subsynth_plain", subsynth_plain),
+    ("subsynth_with_containers", subsynth_with_containers),
+    ("subsynth_plain_multiout", subsynth_plain_multiout),
+];
+
+/// Returns the preset called `name`, if there is one.
+pub fn named(name: &str) -> Option<Preset> {
+    PRESETS.iter().find(|(n, _)| *n == name).map(|(_, f)| *f)
+}
+
 pub fn subsynth_plain(cg: &mut ControlGraph) {
     // Create 440hz and 220hz oscillators
     let sine_osc_1 = cg.connect_const_new(440.0, Sine);
@@ -7,9 +7,15 @@ use std::{fs::File, io::Write, path::PathBuf};
 use petgraph::graph::{EdgeIndex, NodeIndex};
 
 use crate::container::*;
-use crate::control::ControlGraph;
+use crate::control::{format, ControlGraph, Instruction, PortMerge, FORMAT_VERSION};
+use crate::error::{EnvelopeError, GraphError, LoadError};
+use crate::handoff::Handoff;
+use crate::import;
 use crate::node::*;
-use crate::presets::preset;
+use crate::patch;
+use crate::pool::ThreadPool;
+use crate::presets::{preset, Preset};
+use crate::Sample;
 use crate::{assert_glicol_ref_eq, presets};
 
 mod common;
@@ -150,3 +156,1767 @@ fn disconnect() {
 
     assert_eq!(cg.next_sample().l(), 2.0);
 }
+
+#[test]
+fn subsynth_process_block() {
+    for f in [
+        presets::subsynth_plain,
+        presets::subsynth_with_containers,
+        presets::subsynth_plain_multiout,
+    ] {
+        let mut cg1 = preset(44100, f);
+        let mut cg2 = preset(44100, f);
+
+        let s1 = common::cg_samples::<256>(&mut cg1);
+        let s2 = common::cg_block_samples::<256>(&mut cg2, 60);
+
+        if s1 != s2 {
+            panic!(
+                "{}",
+                common::nonmatching_report::<256>(
+                    &s2,
+                    &s1,
+                    &common::eq_matches::<256>(&s2, &s1, 1)
+                )
+            );
+        }
+    }
+}
+
+#[test]
+fn shared_stateful_node() {
+    let mut cg = preset(44100, |cg| {
+        let sine = cg.connect_const_new(440.0, Sine);
+        let add = cg.connect_many_new(&[sine, sine], Add);
+        cg.connect_ex_aout(add);
+    });
+
+    assert_glicol_ref_eq!(
+        within epsilon * 26:
+        &mut cg * 256 == "~s1: sin 440\no: ~s1 >> mul 2"
+    );
+}
+
+#[test]
+fn reset_phase_resets_state() {
+    let mut cg = preset(44100, presets::subsynth_plain);
+
+    let s1 = common::cg_samples::<256>(&mut cg);
+    cg.reset_phase();
+    let s2 = common::cg_samples::<256>(&mut cg);
+
+    if s1 != s2 {
+        panic!(
+            "{}",
+            common::nonmatching_report::<256>(&s2, &s1, &common::eq_matches::<256>(&s2, &s1, 1))
+        );
+    }
+}
+
+#[test]
+fn saw_polyblep() {
+    let mut cg = preset(44100, |cg| {
+        let saw = cg.connect_const_new(441.0, Saw);
+        cg.connect_ex_aout(saw);
+    });
+
+    assert_glicol_ref_eq!(
+        within epsilon * 70 except 5:
+        &mut cg * 256 == "o: saw 441"
+    );
+}
+
+#[test]
+fn saw_polyblep_fm() {
+    let mut cg = preset(44100, |cg| {
+        let lfo = cg.connect_const_new(2.0, Sine);
+        let depth = cg.connect_const_new(100.0, Mul);
+        cg.connect(lfo, depth, 1);
+        let freq = cg.connect_const_new(441.0, Add);
+        cg.connect(depth, freq, 1);
+        let saw = cg.connect_ex_new(freq, Saw);
+        cg.connect_ex_aout(saw);
+    });
+
+    assert_glicol_ref_eq!(
+        within epsilon * 70 except 5:
+        &mut cg * 256 == "~m: sin 2 >> mul 100 >> add 441\no: saw ~m"
+    );
+}
+
+#[test]
+fn square_polyblep() {
+    let mut cg = preset(44100, |cg| {
+        let square = cg.connect_const_new(441.0, Square);
+        cg.connect_ex_aout(square);
+    });
+
+    assert_glicol_ref_eq!(
+        within epsilon * 70 except 11:
+        &mut cg * 256 == "o: squ 441"
+    );
+}
+
+#[test]
+fn pulse_polyblep() {
+    let mut cg = preset(44100, |cg| {
+        let pulse = cg.connect_const_new(441.0, Pulse);
+        cg.connect_const_ex_port(0.5, pulse, 1);
+        cg.connect_ex_aout(pulse);
+    });
+
+    assert_glicol_ref_eq!(
+        within epsilon * 70 except 11:
+        &mut cg * 256 == "o: squ 441"
+    );
+}
+
+#[test]
+fn triangle_polyblamp() {
+    let mut cg = preset(44100, |cg| {
+        let triangle = cg.connect_const_new(441.0, Triangle);
+        cg.connect_ex_aout(triangle);
+    });
+
+    assert_glicol_ref_eq!(
+        within epsilon * 70 except 11:
+        &mut cg * 256 == "o: tri 441"
+    );
+}
+
+#[test]
+fn connect_would_cycle() {
+    let mut cg = preset(44100, presets::subsynth_plain);
+
+    assert_eq!(
+        cg.try_connect(NodeIndex::new(9), NodeIndex::new(2), 0),
+        Err(GraphError::WouldCycle {
+            src: NodeIndex::new(9),
+            dest: NodeIndex::new(2)
+        })
+    );
+}
+
+#[test]
+fn connect_no_such_node() {
+    let mut cg = preset(44100, presets::subsynth_plain);
+
+    cg.remove(NodeIndex::new(1));
+
+    assert_eq!(
+        cg.try_connect(NodeIndex::new(1), NodeIndex::new(2), 0),
+        Err(GraphError::NoSuchNode(NodeIndex::new(1)))
+    );
+    assert!(cg.try_get_node(NodeIndex::new(1)).is_err());
+    assert!(cg.try_get_node_val(NodeIndex::new(1)).is_err());
+}
+
+#[test]
+fn aout_unconnected() {
+    let mut cg = preset(44100, presets::subsynth_plain);
+
+    cg.disconnect(EdgeIndex::new(8));
+
+    assert_eq!(cg.try_next_sample(), Err(GraphError::AoutUnconnected));
+    assert_eq!(
+        cg.try_process_block(&mut [Sample::default(); 4]),
+        Err(GraphError::AoutUnconnected)
+    );
+}
+
+#[test]
+fn remove_parent_without_reconnecting() {
+    let mut cg = preset(44100, presets::subsynth_plain);
+
+    cg.next_sample();
+    cg.remove(NodeIndex::new(1));
+
+    assert!(cg.try_next_sample().is_ok());
+}
+
+#[test]
+fn connect_port_out_of_range() {
+    let mut cg = preset(44100, presets::subsynth_plain);
+    let c = cg.insert(c(1.0));
+
+    assert_eq!(
+        cg.try_connect(c, NodeIndex::new(2), 1),
+        Err(GraphError::PortOutOfRange {
+            node: NodeIndex::new(2),
+            port: 1,
+            len: 1
+        })
+    );
+}
+
+#[test]
+fn connect_port_occupied() {
+    let mut cg = preset(44100, presets::subsynth_plain);
+    let c = cg.insert(c(1.0));
+
+    assert_eq!(
+        cg.try_connect(c, NodeIndex::new(2), 0),
+        Err(GraphError::PortOccupied {
+            node: NodeIndex::new(2),
+            port: 0
+        })
+    );
+    assert_eq!(
+        cg.try_connect_ex_aout(c),
+        Err(GraphError::PortOccupied {
+            node: NodeIndex::new(0),
+            port: 0
+        })
+    );
+}
+
+#[test]
+fn connect_port_sum() {
+    let mut cg = preset(44100, |cg| {
+        cg.set_port_merge(PortMerge::Sum);
+
+        let sine_osc_1 = cg.connect_const_new(440.0, Sine);
+        let sine_osc_2 = cg.connect_const_new(220.0, Sine);
+
+        let mulhalf = cg.connect_const_new(0.5, Mul);
+        cg.connect(sine_osc_1, mulhalf, 1);
+        cg.connect(sine_osc_2, mulhalf, 1);
+
+        cg.connect_ex_aout(mulhalf);
+    });
+
+    assert_glicol_ref_eq!(
+        within epsilon * 26:
+        &mut cg * 256 == "~s1: sin 440\n~s2: sin 220\no: ~s1 >> add ~s2 >> mul 0.5"
+    );
+}
+
+#[test]
+fn connect_named() {
+    let mut cg = preset(44100, |cg| {
+        let c_440 = cg.insert(c(440.0));
+        let sine_osc_1 = cg.insert(Sine);
+        cg.connect_named(c_440, sine_osc_1, "Frequency");
+
+        let c_220 = cg.insert(c(220.0));
+        let sine_osc_2 = cg.insert(Sine);
+        cg.connect_named(c_220, sine_osc_2, "Frequency");
+
+        let (sub_in, sub_out) = cg.insert_container(Sub);
+        cg.connect_ex_ex(sine_osc_2, sub_in.named("RHS"));
+        cg.connect_ex_ex(sine_osc_1, sub_in.named("LHS"));
+
+        let (div_in, div_out) = cg.insert_container(Div);
+        cg.connect_ex_ex(sub_out.named("Difference"), div_in.named("Dividend"));
+        cg.connect_const_ex(2.0, div_in.named("Divisor"));
+
+        cg.connect_ex_aout(div_out.named("Quotient"));
+    });
+
+    assert_glicol_ref_eq!(
+        within epsilon * 26:
+        &mut cg * 256 == "~s1: sin 440\n~s2: sin 220\no: ~s2 >> mul -1 >> add ~s1 >> mul 0.5"
+    );
+}
+
+#[test]
+fn connect_named_no_such_port() {
+    let mut cg = ControlGraph::new(44100);
+    let c = cg.insert(c(1.0));
+    let pulse = cg.insert(Pulse);
+
+    assert_eq!(
+        cg.try_connect_named(c, pulse, "Amplitude"),
+        Err(GraphError::NoSuchPort {
+            node: pulse,
+            label: "Amplitude".into(),
+            valid: vec!["Frequency".into(), "Width".into()]
+        })
+    );
+
+    let (sub_in, _) = cg.insert_container(Sub);
+    assert_eq!(
+        sub_in.try_named("Minuend"),
+        Err(GraphError::NoSuchContainerPort {
+            label: "Minuend".into(),
+            valid: vec!["LHS".into(), "RHS".into()]
+        })
+    );
+}
+
+#[test]
+fn multi_output_split() {
+    let mut cg = preset(44100, |cg| {
+        let stereo = cg.insert(Const(Sample::stereo(1.0, 2.0)));
+        let split = cg.connect_ex_new(stereo, Split);
+
+        let sub = cg.insert(Add);
+        cg.connect_ports(split, 1, sub, 0);
+        cg.connect_labels(split, "Left", sub, "RHS");
+
+        let mul = cg.connect_const_new(0.5, Mul);
+        cg.connect_ports(split, 0, mul, 1);
+
+        let add = cg.connect_many_new(&[sub, mul], Add);
+        cg.connect_ex_aout(add);
+    });
+
+    record_graph("multi_output_split", &cg);
+
+    assert_eq!(cg.next_sample(), Sample::mono(3.5));
+
+    let mut block = [Sample::default(); 4];
+    cg.process_block(&mut block);
+    assert_eq!(block, [Sample::mono(3.5); 4]);
+}
+
+#[test]
+fn connect_output_out_of_range() {
+    let mut cg = ControlGraph::new(44100);
+    let split = cg.insert(Split);
+    let add = cg.insert(Add);
+
+    assert_eq!(
+        cg.try_connect_ports(split, 2, add, 0),
+        Err(GraphError::OutputOutOfRange {
+            node: split,
+            port: 2,
+            len: 2
+        })
+    );
+}
+
+#[test]
+fn feedback_accumulator() {
+    let mut cg = preset(44100, |cg| {
+        let add = cg.connect_const_new(1.0, Add);
+        cg.connect_feedback(add, 0, add, 1, 1);
+        cg.connect_ex_aout(add);
+    });
+
+    record_graph("feedback_accumulator", &cg);
+
+    for i in 1..=4 {
+        assert_eq!(cg.next_sample(), Sample::mono(i as f64));
+    }
+
+    let mut block = [Sample::default(); 4];
+    cg.process_block(&mut block);
+    assert_eq!(block.map(|s| s.l()), [5.0, 6.0, 7.0, 8.0]);
+}
+
+#[test]
+fn feedback_comb_process_block() {
+    // y[n] = sin[n] + 0.5 * y[n - 7], evaluated with blocks longer than the delay
+    let comb = |cg: &mut ControlGraph| {
+        let sine = cg.connect_const_new(440.0, Sine);
+        let add = cg.connect_ex_new(sine, Add);
+        cg.connect_ex_aout(add);
+
+        let mul = cg.connect_const_new_port(0.5, Mul, 1);
+        cg.connect_feedback(add, 0, mul, 0, 7);
+        cg.connect(mul, add, 1);
+    };
+
+    let mut cg1 = preset(44100, comb);
+    let mut cg2 = preset(44100, comb);
+
+    let s1 = common::cg_samples::<256>(&mut cg1);
+    let s2 = common::cg_block_samples::<256>(&mut cg2, 60);
+
+    if s1 != s2 {
+        panic!(
+            "{}",
+            common::nonmatching_report::<256>(&s2, &s1, &common::eq_matches::<256>(&s2, &s1, 1))
+        );
+    }
+}
+
+#[test]
+fn feedback_without_delay() {
+    let mut cg = ControlGraph::new(44100);
+    let add = cg.insert(Add);
+
+    assert_eq!(
+        cg.try_connect_feedback(add, 0, add, 1, 0),
+        Err(GraphError::FeedbackWithoutDelay {
+            src: add,
+            dest: add
+        })
+    );
+}
+
+#[test]
+fn patch_round_trip() {
+    for (name, f) in presets::PRESETS {
+        let mut cg1 = preset(44100, f);
+        let text = patch::write(&cg1).unwrap();
+        let mut cg2 = patch::parse(44100, &text).unwrap_or_else(|e| panic!("{name}: {e}"));
+
+        assert_eq!(patch::write(&cg2).unwrap(), text, "{name}");
+        assert_eq!(
+            cg2.get_container_count(),
+            cg1.get_container_count(),
+            "{name}"
+        );
+
+        let s1 = common::cg_samples::<256>(&mut cg1);
+        let s2 = common::cg_samples::<256>(&mut cg2);
+        assert_eq!(s1, s2, "{name}");
+    }
+}
+
+#[test]
+fn patch_write_unknown_node() {
+    // nodes that `parse` can't construct would be lost on the way back, so they aren't written
+    let mut cg = ControlGraph::new(44100);
+    let custom = cg.connect_const_new(3.0, Lookalike("Custom".into()));
+    cg.connect_const_ex_port(1.0, custom, 1);
+    cg.connect_ex_aout(custom);
+
+    let e = patch::write(&cg).unwrap_err();
+    assert_eq!(e.message, "unknown node `Custom`");
+    assert_eq!(e.line, 2);
+}
+
+#[test]
+fn patch_parse() {
+    let mut cg = patch::parse(
+        44100,
+        r#"
+        container 0 "Comb filter"
+        container 1 Inner in 0
+
+        node 1 Constant 0.25 0.75 # stereo
+        node 2 Add in 1
+        node 3 Feedback 2 in 0
+        node 4 ContainerOutput "Out \"put\"" in 1
+
+        edge 1:0 -> 2:LHS
+        edge 3:Output -> 2:1
+        edge 2:0 -> 4:0
+        edge 4:0 -> aout
+        feedback 2:0 -> 3
+        "#,
+    )
+    .unwrap();
+
+    record_graph("patch_parse", &cg);
+
+    assert_eq!(cg.get_container_ident(0), "Comb filter");
+    assert_eq!(cg.get_container_parent(1), Some(0));
+    assert_eq!(cg.get_container_member_indexes(0).count(), 3);
+    assert_eq!(
+        cg.get_node(NodeIndex::new(4)).get_input_labels()[0],
+        "Out \"put\""
+    );
+
+    let samples = (0..4).map(|_| cg.next_sample()).collect::<Vec<_>>();
+    assert_eq!(
+        samples,
+        [
+            Sample::stereo(0.25, 0.75),
+            Sample::stereo(0.25, 0.75),
+            Sample::stereo(0.5, 1.5),
+            Sample::stereo(0.5, 1.5),
+        ]
+    );
+}
+
+#[test]
+fn patch_parse_errors() {
+    let err = |src: &str| {
+        let e = patch::parse(44100, src).unwrap_err();
+        (e.line, e.column, e.message)
+    };
+
+    assert_eq!(
+        err("node 1 Sine\n  node 2 Sinw"),
+        (2, 10, "unknown node `Sinw`".into())
+    );
+    assert_eq!(err("node 1 Constant"), (1, 16, "expected a value".into()));
+    assert_eq!(
+        err("node 1 Sine\nedge 1:0 -> 2:0"),
+        (2, 13, "node 2 isn't declared".into())
+    );
+    assert_eq!(
+        err("node 1 Sine\nedge 1:0 -> 1:Phase"),
+        (
+            2,
+            15,
+            "node 1 has no port named \"Phase\" (expected one of: Frequency)".into()
+        )
+    );
+    // ids are named as written, even though nodes are renumbered when they're inserted
+    assert_eq!(
+        err("node 7 Sine\nnode 3 Sine\nedge 7:0 -> 3:0\nedge 3:0 -> 7:0"),
+        (4, 6, "adding edge 3 -> 7 would cause a cycle".into())
+    );
+    assert_eq!(
+        err("node 5 Constant 1\nedge 5:0 -> aout\nedge 5:0 -> aout"),
+        (3, 6, "port 0 of node aout is already connected".into())
+    );
+    assert_eq!(
+        err("node 1 Sine \"unterminated"),
+        (1, 13, "unterminated string".into())
+    );
+    assert_eq!(
+        err("node 1 Biquad Bandstop"),
+        (
+            1,
+            15,
+            "expected one of Lowpass, Highpass, Bandpass, Notch, Peak, LowShelf, HighShelf, \
+             found `Bandstop`"
+                .into()
+        )
+    );
+    assert_eq!(
+        err("container 1 Sub"),
+        (
+            1,
+            11,
+            "expected container 0, since containers are numbered in order".into()
+        )
+    );
+}
+
+#[test]
+fn import_glicol_subsynth() {
+    let src = "~s1: sin 440\n~s2: sin 220\no: ~s2 >> mul -1 >> add ~s1 >> mul 0.5";
+    let mut cg1 = import::glicol::parse(44100, src).unwrap();
+    let mut cg2 = preset(44100, presets::subsynth_plain);
+
+    record_graph("import_glicol_subsynth", &cg1);
+
+    assert_eq!(
+        common::cg_samples::<256>(&mut cg1),
+        common::cg_samples::<256>(&mut cg2)
+    );
+
+    let mut cg = import::glicol::parse(44100, src).unwrap();
+    assert_glicol_ref_eq!(
+        within epsilon * 26:
+        &mut cg * 256 == src
+    );
+}
+
+#[test]
+fn import_glicol_references() {
+    // `~s3` is defined after `o`, and reads its frequency from `~s2`
+    let src = "o: ~s3 >> add ~s1 >> mul 0.5 // comment\n\n~s1: sin 220\n~s2: sin 220\n~s3: sin ~s2";
+    let mut cg = import::glicol::parse(44100, src).unwrap();
+
+    assert_glicol_ref_eq!(
+        within epsilon * 26:
+        &mut cg * 256 == "~s1: sin 220\n~s2: sin 220\n~s3: sin ~s2\no: ~s3 >> add ~s1 >> mul 0.5"
+    );
+}
+
+#[test]
+fn import_glicol_errors() {
+    let err = |src: &str| {
+        let e = import::glicol::parse(44100, src).err().unwrap();
+        (e.line, e.column, e.message)
+    };
+
+    assert_eq!(
+        err("~a: sin 440\no: ~a >> lpf 1000 1.0"),
+        (
+            2,
+            10,
+            "unsupported glicol node `lpf` (expected one of: sin, constsig, mul, add)".into()
+        )
+    );
+    // dagrid's oscillators are band-limited, unlike glicol's, so they'd sound different
+    assert_eq!(
+        err("o: saw 440"),
+        (
+            1,
+            4,
+            "unsupported glicol node `saw` (expected one of: sin, constsig, mul, add)".into()
+        )
+    );
+    assert_eq!(
+        err("o: sin ~missing"),
+        (1, 8, "`~missing` isn't defined".into())
+    );
+    assert_eq!(
+        err("~a: sin ~b\n~b: sin ~a\no: ~a"),
+        (2, 9, "`~a` refers back to itself".into())
+    );
+    assert_eq!(
+        err("o: sin 440 >> mul \"0.5\""),
+        (
+            1,
+            19,
+            "unsupported parameter `\"0.5\"`; only numbers and references are supported".into()
+        )
+    );
+    assert_eq!(
+        err("~a: sin 440"),
+        (1, 1, "there's no output chain, such as `o: sin 440`".into())
+    );
+}
+
+#[test]
+fn set_source() {
+    let mut cg = preset(44100, |cg| {
+        let velocity = cg.insert(Velocity);
+        let gate = cg.insert(Gate);
+        let mul = cg.connect_many_new(&[velocity, gate], Mul);
+        cg.connect_ex_aout(mul);
+    });
+
+    assert_eq!(cg.next_sample(), Sample::mono(0.0));
+
+    cg.set_source(Source::Velocity, Sample::mono(0.5));
+    cg.set_source(Source::Gate, Sample::mono(1.0));
+
+    let mut block = [Sample::default(); 4];
+    cg.process_block(&mut block);
+    assert_eq!(block, [Sample::mono(0.5); 4]);
+
+    // sources keep their values, unlike the rest of the graph's state
+    cg.reset_phase();
+    assert_eq!(cg.next_sample(), Sample::mono(0.5));
+
+    let mut clone = cg.try_clone().unwrap();
+    assert_eq!(clone.next_sample(), Sample::mono(0.0));
+}
+
+#[test]
+fn midi_sources() {
+    let text = "node 1 NoteFrequency\nnode 2 CC 74\nnode 3 CC 1\nnode 4 Add\nnode 5 Add\n\
+                edge 1:0 -> 4:0\nedge 2:0 -> 4:1\nedge 4:0 -> 5:0\nedge 3:0 -> 5:1\nedge 5:0 -> aout\n";
+    let mut cg = patch::parse(44100, text).unwrap();
+
+    assert_eq!(
+        patch::write(&cg).unwrap().lines().nth(1),
+        Some("node 2 CC 74")
+    );
+
+    cg.set_source(Source::NoteFrequency, Sample::mono(440.0));
+    cg.set_source(Source::Cc(74), Sample::mono(0.25));
+    assert_eq!(cg.next_sample(), Sample::mono(440.25));
+
+    let mut block = [Sample::default(); 4];
+    cg.set_source(Source::Cc(1), Sample::mono(0.5));
+    cg.process_block(&mut block);
+    assert_eq!(block, [Sample::mono(440.75); 4]);
+}
+
+#[test]
+fn macros() {
+    let text = "node 1 ParamSource 3\nnode 2 ParamSource 0\nnode 3 Add\n\n\
+                edge 1:0 -> 3:0\nedge 2:0 -> 3:1\nedge 3:0 -> aout\n\n\
+                macro 3 \"Cutoff frequency\" 20 420 \" Hz\"\n";
+    let mut cg = patch::parse(44100, text).unwrap();
+
+    assert_eq!(patch::write(&cg).unwrap(), text);
+    assert_eq!(cg.get_macro(3).name, "Cutoff frequency");
+    assert_eq!(cg.get_macro(3).unmap(220.0), 0.5);
+    assert_eq!(cg.get_macro(0), &Macro::default());
+
+    cg.set_macro_value(3, 0.5);
+    cg.set_macro_value(0, 0.25);
+    assert_eq!(cg.next_sample(), Sample::mono(220.25));
+
+    assert!(patch::parse(44100, "node 1 ParamSource 16").is_err());
+    assert!(patch::parse(44100, "macro 16 Cutoff 0 1").is_err());
+}
+
+#[test]
+fn load_fixtures() {
+    // saved before the format was versioned
+    let v0: [(&str, &[u8], Preset); 2] = [
+        (
+            "subsynth_plain",
+            include_bytes!("fixtures/v0_subsynth_plain.bin"),
+            presets::subsynth_plain,
+        ),
+        (
+            "subsynth_with_containers",
+            include_bytes!("fixtures/v0_subsynth_with_containers.bin"),
+            presets::subsynth_with_containers,
+        ),
+    ];
+
+    for (name, data, f) in v0 {
+        let mut loaded = ControlGraph::load(44100, data).unwrap_or_else(|e| panic!("{name}: {e}"));
+        let mut cg = preset(44100, f);
+
+        assert_eq!(
+            loaded.get_container_count(),
+            cg.get_container_count(),
+            "{name}"
+        );
+
+        let s1 = common::cg_samples::<256>(&mut loaded);
+        let s2 = common::cg_samples::<256>(&mut cg);
+        assert_eq!(s1, s2, "{name}");
+    }
+
+    let mut cg =
+        ControlGraph::load(44100, include_bytes!("fixtures/v1_feedback_macros.bin")).unwrap();
+
+    assert_eq!(cg.get_macro(2).max, 2.0);
+    assert_eq!(cg.get_feedback_edges().len(), 1);
+
+    cg.set_macro_value(2, 0.5);
+    let samples = (0..3).map(|_| cg.next_sample()).collect::<Vec<_>>();
+    assert_eq!(
+        samples,
+        [
+            Sample::stereo(0.25, 0.75),
+            Sample::stereo(0.25, 0.75),
+            Sample::stereo(0.5, 1.5),
+        ]
+    );
+}
+
+#[test]
+fn migrations_are_frozen() {
+    // each migration has to keep producing its own version once the live layout moves on, so
+    // these were saved by `v0_to_v1` rather than `ControlGraph::save`
+    let fixtures: [(&[u8], &[u8]); 2] = [
+        (
+            include_bytes!("fixtures/v0_subsynth_plain.bin"),
+            include_bytes!("fixtures/v1_subsynth_plain.bin"),
+        ),
+        (
+            include_bytes!("fixtures/v0_subsynth_with_containers.bin"),
+            include_bytes!("fixtures/v1_subsynth_with_containers.bin"),
+        ),
+    ];
+
+    for (v0, v1) in fixtures {
+        assert_eq!(format::v0_to_v1(v0).unwrap(), v1[8..]);
+        assert!(ControlGraph::load(44100, v1).is_ok());
+    }
+}
+
+#[test]
+fn load_errors() {
+    let mut data = preset(44100, presets::subsynth_plain).save().unwrap();
+    assert_eq!(&data[..4], b"DGRD");
+
+    data[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    assert_eq!(
+        ControlGraph::load(44100, &data).unwrap_err(),
+        LoadError::NewerVersion(FORMAT_VERSION + 1)
+    );
+
+    assert!(matches!(
+        ControlGraph::load(44100, b"DGRD\x01"),
+        Err(LoadError::Decode(_))
+    ));
+}
+
+#[test]
+fn handoff() {
+    use std::sync::Arc;
+
+    let handoff = Handoff::new();
+    let (zero, first, second) = (Arc::new(0), Arc::new(1), Arc::new(2));
+
+    assert!(!handoff.receive(|_| unreachable!()));
+
+    // values that are never received are dropped by the next publish
+    handoff.publish(first.clone());
+    handoff.publish(second.clone());
+    assert_eq!(Arc::strong_count(&first), 1);
+
+    let mut current = zero.clone();
+    assert!(handoff.receive(|new| std::mem::swap(new, &mut current)));
+    assert_eq!(current, second);
+    assert!(!handoff.receive(|_| unreachable!()));
+
+    // the replaced value is retired until it's collected
+    assert_eq!(Arc::strong_count(&zero), 2);
+    handoff.collect();
+    assert_eq!(Arc::strong_count(&zero), 1);
+
+    handoff.publish(first.clone());
+    assert!(handoff.receive(|new| std::mem::swap(new, &mut current)));
+    assert_eq!(current, first);
+
+    drop(handoff);
+    assert_eq!(Arc::strong_count(&second), 1);
+
+    // values are only ever moved between threads, so they needn't be `Sync`
+    fn shareable<T: Send + Sync>() {}
+    shareable::<Handoff<std::cell::Cell<u8>>>();
+}
+
+#[test]
+fn carry_state() {
+    let text = "node 1 Constant 0.5\nnode 2 Add\nnode 3 Feedback 3\nnode 4 Saw\nnode 5 Constant 110\n\
+                node 6 Add\n\
+                edge 1:0 -> 2:0\nedge 3:0 -> 2:1\nedge 5:0 -> 4:0\nedge 2:0 -> 6:0\nedge 4:0 -> 6:1\n\
+                edge 6:0 -> aout\nfeedback 2:0 -> 3\n";
+    let mut cg = patch::parse(44100, text).unwrap();
+    let mut block = [Sample::default(); 100];
+    cg.process_block(&mut block);
+
+    // an edit that doesn't change the output
+    let mut edited = cg.try_clone().unwrap();
+    edited.insert(c(1.0));
+    edited.try_prepare(64).unwrap();
+    edited.carry_state_from(&cg);
+
+    let mut expected = [Sample::default(); 64];
+    let mut carried = [Sample::default(); 64];
+    cg.process_block(&mut expected);
+    edited.process_block(&mut carried);
+    assert_eq!(carried, expected);
+}
+
+#[test]
+fn compile_matches_interpreter() {
+    let feedback =
+        "node 1 Constant 0.5\nnode 2 Add\nnode 3 Feedback 3\nnode 4 Saw\nnode 5 Constant 110\n\
+                    node 6 Split\nnode 7 Multiply\nnode 8 Inverse\nnode 9 Add\n\
+                    edge 1:0 -> 2:0\nedge 3:0 -> 2:1\nedge 5:0 -> 4:0\nedge 2:0 -> 6:0\n\
+                    edge 6:Left -> 7:0\nedge 4:0 -> 7:1\nedge 6:Right -> 8:0\nedge 7:0 -> 9:0\n\
+                    edge 8:0 -> 9:1\nedge 9:0 -> aout\nfeedback 2:0 -> 3\n";
+
+    let mut graphs = common::preset_graphs(44100);
+    graphs.push(("feedback", patch::parse(44100, feedback).unwrap()));
+
+    for (name, cg) in graphs {
+        let mut interpreted = cg.try_clone().unwrap();
+        let mut program = cg.compile().unwrap();
+
+        let mut expected = [Sample::default(); 256];
+        let mut compiled = [Sample::default(); 256];
+        for sample in &mut expected {
+            *sample = interpreted.next_sample();
+        }
+        program.process(&mut compiled);
+
+        assert_eq!(
+            common::to_bits(&compiled),
+            common::to_bits(&expected),
+            "{name}"
+        );
+    }
+
+    // instructions read straight from the registers of their inputs
+    let program = patch::parse(44100, feedback).unwrap().compile().unwrap();
+    assert!(program
+        .get_instructions()
+        .iter()
+        .any(|i| matches!(i, Instruction::Split { .. })));
+
+    // sources keep working once compiled
+    let mut cg = patch::parse(44100, "node 1 Gate\nedge 1:0 -> aout\n").unwrap();
+    cg.set_source(Source::Gate, Sample::mono(1.0));
+    let mut program = cg.compile().unwrap();
+    assert_eq!(program.next_sample(), Sample::mono(1.0));
+    program.set_source(Source::Gate, Sample::mono(0.0));
+    assert_eq!(program.next_sample(), Sample::mono(0.0));
+
+    // only the built-in nodes are lowered, whatever other nodes call themselves
+    let mut cg = ControlGraph::new(44100);
+    let sub = cg.connect_const_new(3.0, Lookalike("Add".into()));
+    cg.connect_const_ex_port(1.0, sub, 1);
+    cg.connect_ex_aout(sub);
+    let mut program = cg.compile().unwrap();
+    assert!(matches!(
+        program.get_instructions()[..],
+        [.., Instruction::Call { .. }]
+    ));
+    assert_eq!(program.next_sample(), Sample::mono(2.0));
+}
+
+/// A node that shares its ident with a built-in node, but subtracts.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Lookalike(String);
+
+#[typetag::serde]
+impl Node for Lookalike {
+    fn get_ident(&self) -> &str {
+        &self.0
+    }
+
+    fn get_input_labels(&self) -> &[Cow<'static, str>] {
+        &[Cow::Borrowed("LHS"), Cow::Borrowed("RHS")]
+    }
+
+    fn process(
+        &self,
+        inputs: &[Sample],
+        _state: &mut [Sample],
+        _phase: u64,
+        _sample_rate: u32,
+    ) -> Sample {
+        inputs[0] - inputs[1]
+    }
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn jit_matches_program() {
+    // every oscillator, stereo and modulated, with the pulse width swept past 0..1
+    let oscillators = "node 1 NoteFrequency\nnode 2 Constant 1 1.5\nnode 3 Multiply\n\
+                       node 4 Saw\nnode 5 Square\nnode 6 Triangle\nnode 7 Pulse\nnode 8 Sine\n\
+                       node 9 Constant 0.3\nnode 10 Multiply\nnode 11 Constant 1.5\n\
+                       node 12 Add\nnode 13 Add\nnode 14 Add\nnode 15 Inverse\nnode 16 Add\n\
+                       node 17 Split\nnode 18 Multiply\n\
+                       edge 1:0 -> 3:0\nedge 2:0 -> 3:1\nedge 3:0 -> 4:0\nedge 3:0 -> 5:0\n\
+                       edge 3:0 -> 6:0\nedge 3:0 -> 7:0\nedge 9:0 -> 8:0\nedge 8:0 -> 10:0\n\
+                       edge 11:0 -> 10:1\nedge 10:0 -> 7:1\nedge 4:0 -> 12:0\nedge 5:0 -> 12:1\n\
+                       edge 6:0 -> 13:0\nedge 7:0 -> 13:1\nedge 12:0 -> 14:0\nedge 13:0 -> 14:1\n\
+                       edge 11:0 -> 15:0\nedge 14:0 -> 16:0\nedge 15:0 -> 16:1\n\
+                       edge 16:0 -> 17:0\nedge 17:Left -> 18:0\nedge 17:Right -> 18:1\n\
+                       edge 18:0 -> aout\n";
+    let feedback = "node 1 Feedback 1\nnode 2 Sine\nnode 3 Add\nnode 4 Constant 110\n\
+                    edge 4:0 -> 2:0\nedge 1:0 -> 3:0\nedge 2:0 -> 3:1\nedge 3:0 -> aout\n\
+                    feedback 3:0 -> 1\n";
+
+    let mut graphs = common::preset_graphs(44100)
+        .into_iter()
+        .map(|(name, cg)| (name, cg, true))
+        .collect::<Vec<_>>();
+    graphs.push((
+        "oscillators",
+        patch::parse(44100, oscillators).unwrap(),
+        true,
+    ));
+    graphs.push(("feedback", patch::parse(44100, feedback).unwrap(), false));
+
+    for (name, mut cg, native) in graphs {
+        cg.set_source(Source::NoteFrequency, Sample::mono(440.0));
+        let mut program = cg.try_clone().unwrap().compile().unwrap();
+        let mut jit = cg.compile().unwrap().jit();
+        assert_eq!(jit.is_native(), native, "{name}");
+
+        // across several chunks, with sources set in between
+        let mut expected = [Sample::default(); 300];
+        let mut compiled = [Sample::default(); 300];
+        for (i, (expected, compiled)) in expected
+            .chunks_mut(100)
+            .zip(compiled.chunks_mut(100))
+            .enumerate()
+        {
+            let freq = Sample::stereo(220.0 * (i + 1) as f64, 5000.0);
+            program.set_source(Source::NoteFrequency, freq);
+            jit.set_source(Source::NoteFrequency, freq);
+
+            program.process(expected);
+            compiled[0] = jit.next_sample();
+            jit.process(&mut compiled[1..]);
+        }
+
+        assert_eq!(
+            common::to_bits(&compiled),
+            common::to_bits(&expected),
+            "{name}"
+        );
+    }
+
+    // nodes that only share an oscillator's ident aren't lowered
+    let mut cg = ControlGraph::new(44100);
+    let lookalike = cg.connect_const_new(3.0, Lookalike("Sine".into()));
+    cg.connect_const_ex_port(1.0, lookalike, 1);
+    cg.connect_ex_aout(lookalike);
+    let mut jit = cg.compile().unwrap().jit();
+    assert!(!jit.is_native());
+    assert_eq!(jit.next_sample(), Sample::mono(2.0));
+}
+
+#[test]
+fn thread_pool() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    for workers in [0, 1, 3] {
+        let pool = ThreadPool::new(workers);
+        assert_eq!(pool.workers(), workers);
+
+        // every task runs exactly once, in every job
+        for tasks in [0, 1, 7, 1000] {
+            let counts = (0..tasks).map(|_| AtomicUsize::new(0)).collect::<Vec<_>>();
+            for _ in 0..10 {
+                pool.run(tasks, &|i| {
+                    counts[i].fetch_add(1, Ordering::Relaxed);
+                });
+            }
+
+            assert!(counts.iter().all(|c| c.load(Ordering::Relaxed) == 10));
+        }
+
+        // jobs started from inside a job run on the thread that starts them
+        let count = AtomicUsize::new(0);
+        pool.run(4, &|_| {
+            pool.run(4, &|_| {
+                count.fetch_add(1, Ordering::Relaxed);
+            });
+        });
+        assert_eq!(count.load(Ordering::Relaxed), 16);
+    }
+}
+
+#[test]
+fn parallel_matches_sequential() {
+    // enough independent oscillators for a level to be split up
+    let wide = |cg: &mut ControlGraph| {
+        let mut sum = cg.insert(c(0.0));
+        for i in 0..8 {
+            let osc = if i % 2 == 0 {
+                cg.connect_const_new(110.0 * (i + 1) as f64, Saw)
+            } else {
+                cg.connect_const_new(110.0 * (i + 1) as f64, Sine)
+            };
+            sum = cg.connect_many_new(&[sum, osc], Add);
+        }
+
+        let split = cg.connect_ex_new(sum, Split);
+        let mul = cg.insert(Mul);
+        cg.connect_ports(split, 0, mul, 0);
+        cg.connect_ports(split, 1, mul, 1);
+        cg.connect_ex_aout(mul);
+    };
+    let feedback = "node 1 Feedback 64\nnode 2 Sine\nnode 3 Saw\nnode 4 Add\nnode 5 Add\n\
+                    node 6 Constant 220\nedge 6:0 -> 2:0\nedge 6:0 -> 3:0\nedge 2:0 -> 4:0\n\
+                    edge 3:0 -> 4:1\nedge 4:0 -> 5:0\nedge 1:0 -> 5:1\nedge 5:0 -> aout\n\
+                    feedback 4:0 -> 1\n";
+
+    let mut graphs = common::preset_graphs(44100);
+    graphs.push(("wide", preset(44100, wide)));
+    graphs.push(("feedback", patch::parse(44100, feedback).unwrap()));
+
+    let pool = ThreadPool::new(3);
+
+    for (name, cg) in graphs {
+        // preparing evaluates a sample, so both graphs are prepared
+        let mut sequential = cg.try_clone().unwrap();
+        let mut parallel = cg;
+        sequential.try_prepare(1024).unwrap();
+        parallel.try_prepare(1024).unwrap();
+
+        for block_len in [1, 64, 512, 1024, 100] {
+            let mut expected = vec![Sample::default(); block_len];
+            let mut actual = vec![Sample::default(); block_len];
+            sequential.process_block(&mut expected);
+            parallel.process_block_parallel(&mut actual, &pool);
+
+            assert_eq!(
+                common::to_bits(&actual),
+                common::to_bits(&expected),
+                "{name}/{block_len}"
+            );
+        }
+    }
+}
+
+#[test]
+fn optimize_matches_unoptimized() {
+    // a pure subgraph of constants, and a feedback loop that doesn't lead to `aout`
+    let folded = "node 1 Constant 2\nnode 2 Constant 3 4\nnode 3 Add\nnode 4 Inverse\n\
+                  node 5 Sine\nnode 6 Multiply\nnode 7 Constant 110\nnode 8 Multiply\n\
+                  edge 1:0 -> 3:0\nedge 2:0 -> 3:1\nedge 3:0 -> 4:0\nedge 7:0 -> 8:0\n\
+                  edge 3:0 -> 8:1\nedge 8:0 -> 5:0\nedge 5:0 -> 6:0\nedge 4:0 -> 6:1\n\
+                  edge 6:0 -> aout\n";
+    let dead = "node 1 Feedback 1\nnode 2 Saw\nnode 3 Add\nnode 4 Constant 110\nnode 5 Sine\n\
+                edge 4:0 -> 2:0\nedge 1:0 -> 3:0\nedge 2:0 -> 3:1\nedge 4:0 -> 5:0\n\
+                edge 5:0 -> aout\nfeedback 3:0 -> 1\n";
+
+    let mut graphs = common::preset_graphs(44100);
+    graphs.push(("folded", patch::parse(44100, folded).unwrap()));
+    graphs.push(("dead", patch::parse(44100, dead).unwrap()));
+
+    let unoptimized = |cg: &ControlGraph| {
+        let mut cg = cg.try_clone().unwrap();
+        cg.set_optimize(false);
+        cg
+    };
+
+    for (name, cg) in graphs {
+        let (mut optimized, mut plain) = (cg.try_clone().unwrap(), unoptimized(&cg));
+        let mut expected = [Sample::default(); 256];
+        let mut actual = [Sample::default(); 256];
+        for (expected, actual) in expected.iter_mut().zip(&mut actual) {
+            *expected = plain.next_sample();
+            *actual = optimized.next_sample();
+        }
+        assert_eq!(
+            common::to_bits(&actual),
+            common::to_bits(&expected),
+            "{name}"
+        );
+
+        let (mut optimized, mut plain) = (cg.try_clone().unwrap(), unoptimized(&cg));
+        plain.process_block(&mut expected);
+        optimized.process_block(&mut actual);
+        assert_eq!(
+            common::to_bits(&actual),
+            common::to_bits(&expected),
+            "{name}/block"
+        );
+
+        let mut plain = unoptimized(&cg).compile().unwrap();
+        let mut optimized = cg.compile().unwrap();
+        plain.process(&mut expected);
+        optimized.process(&mut actual);
+        assert_eq!(
+            common::to_bits(&actual),
+            common::to_bits(&expected),
+            "{name}/compiled"
+        );
+
+        assert!(
+            optimized.get_instructions().len() <= plain.get_instructions().len(),
+            "{name}"
+        );
+    }
+
+    let compile = |cg: ControlGraph, optimize: bool| {
+        let mut cg = cg;
+        cg.set_optimize(optimize);
+        cg.compile().unwrap().get_instructions().to_vec()
+    };
+
+    // the negation of `Sub` is fused, and the inverse of `Div`'s constant is folded
+    let containers = preset(44100, presets::subsynth_with_containers);
+    let optimized = compile(containers.try_clone().unwrap(), true);
+    let plain = compile(containers, false);
+    assert!(optimized
+        .iter()
+        .any(|i| matches!(i, Instruction::Sub { .. })));
+    assert!(!optimized
+        .iter()
+        .any(|i| matches!(i, Instruction::Inv { .. } | Instruction::Add { .. })));
+    assert_eq!(optimized.len(), plain.len() - 2);
+
+    // only the sine is left
+    let dead = patch::parse(44100, dead).unwrap();
+    assert_eq!(compile(dead.try_clone().unwrap(), true).len(), 1);
+    assert_eq!(compile(dead, false).len(), 4);
+}
+
+/// Builds a graph that feeds a sine at `probe` Hz through `filter`, with each input in `params`
+/// held constant, and routes output `output` to `aout`.
+fn filter_graph<N: Node + 'static>(
+    filter: N,
+    output: usize,
+    probe: Sample,
+    params: &[(&str, Sample)],
+) -> ControlGraph {
+    let mut cg = ControlGraph::new(48000);
+    let probe = cg.insert(Const(probe));
+    let osc = cg.connect_ex_new(probe, Sine);
+    let filter = cg.insert(filter);
+    cg.connect_named(osc, filter, "Input");
+
+    for &(label, value) in params {
+        let param = cg.insert(Const(value));
+        cg.connect_named(param, filter, label);
+    }
+
+    let aout = cg.get_aout_node();
+    cg.connect_ports(filter, output, aout, 0);
+
+    cg
+}
+
+/// Returns the amplitude of each channel of the output of a [filter_graph] at `probe` Hz, once
+/// it has settled. Measured over 4800 samples, which hold a whole number of cycles of any
+/// multiple of 10 Hz.
+fn filter_response(cg: &mut ControlGraph, probe: Sample) -> Sample {
+    const LEN: usize = 4800;
+
+    for _ in 0..LEN {
+        cg.next_sample();
+    }
+
+    let amplitude = |freq: f64, samples: &[f64]| {
+        let (re, im) = samples
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(re, im), (i, x)| {
+                let (sin, cos) = (std::f64::consts::TAU * freq * i as f64 / 48000.0).sin_cos();
+                (re + x * cos, im - x * sin)
+            });
+
+        2.0 * (re * re + im * im).sqrt() / LEN as f64
+    };
+
+    let samples = (0..LEN).map(|_| cg.next_sample()).collect::<Vec<_>>();
+    let l = samples.iter().map(|s| s.l()).collect::<Vec<_>>();
+    let r = samples.iter().map(|s| s.r()).collect::<Vec<_>>();
+
+    Sample::stereo(amplitude(probe.l(), &l), amplitude(probe.r(), &r))
+}
+
+#[track_caller]
+fn assert_response(mut cg: ControlGraph, probe: Sample, expected: f64, tolerance: f64) {
+    let response = filter_response(&mut cg, probe);
+    assert!(
+        (response.l() - expected).abs() < tolerance && (response.r() - expected).abs() < tolerance,
+        "expected {expected}, found {response}"
+    );
+}
+
+#[test]
+fn biquad_response() {
+    // each channel has its own cutoff, which it's probed at
+    let cutoff = Sample::stereo(1000.0, 2000.0);
+    let butterworth = Sample::mono(std::f64::consts::FRAC_1_SQRT_2);
+    let db = |gain: f64| 10f64.powf(gain / 20.0);
+
+    // (mode, Q, gain, probe, expected amplitude)
+    let cases = [
+        (
+            BiquadMode::Lowpass,
+            butterworth,
+            0.0,
+            cutoff,
+            butterworth.l(),
+        ),
+        (
+            BiquadMode::Highpass,
+            butterworth,
+            0.0,
+            cutoff,
+            butterworth.l(),
+        ),
+        (BiquadMode::Bandpass, Sample::mono(2.0), 0.0, cutoff, 1.0),
+        (BiquadMode::Notch, Sample::mono(1.0), 0.0, cutoff, 0.0),
+        (BiquadMode::Peak, Sample::mono(1.0), 6.0, cutoff, db(6.0)),
+        // shelves are at half their gain at the cutoff
+        (BiquadMode::LowShelf, butterworth, -12.0, cutoff, db(-6.0)),
+        (BiquadMode::HighShelf, butterworth, 12.0, cutoff, db(6.0)),
+        (
+            BiquadMode::Lowpass,
+            butterworth,
+            0.0,
+            Sample::stereo(100.0, 200.0),
+            1.0,
+        ),
+        (
+            BiquadMode::Highpass,
+            butterworth,
+            0.0,
+            Sample::stereo(100.0, 200.0),
+            0.01,
+        ),
+        (
+            BiquadMode::LowShelf,
+            butterworth,
+            -12.0,
+            Sample::mono(20.0),
+            db(-12.0),
+        ),
+    ];
+
+    for (mode, q, gain, probe, expected) in cases {
+        let params = [
+            ("Frequency", cutoff),
+            ("Q", q),
+            ("Gain", Sample::mono(gain)),
+        ];
+        let cg = filter_graph(Biquad(mode), 0, probe, &params);
+        assert_response(cg, probe, expected, 1e-3);
+    }
+
+    // unconnected, Q is that of a Butterworth filter and the gain is 0 dB, so a peak is flat
+    let cg = filter_graph(
+        Biquad(BiquadMode::Peak),
+        0,
+        cutoff,
+        &[("Frequency", cutoff)],
+    );
+    assert_response(cg, cutoff, 1.0, 1e-3);
+
+    let cg = filter_graph(
+        Biquad(BiquadMode::Lowpass),
+        0,
+        cutoff,
+        &[("Frequency", cutoff)],
+    );
+    assert_response(cg, cutoff, butterworth.l(), 1e-3);
+}
+
+#[test]
+fn svf_response() {
+    let cutoff = Sample::stereo(1000.0, 2000.0);
+    let butterworth = 1.0 - std::f64::consts::FRAC_1_SQRT_2;
+
+    // (output, resonance, probe, expected amplitude)
+    let cases = [
+        (0, butterworth, cutoff, std::f64::consts::FRAC_1_SQRT_2),
+        (1, butterworth, cutoff, std::f64::consts::FRAC_1_SQRT_2),
+        (2, butterworth, cutoff, std::f64::consts::FRAC_1_SQRT_2),
+        // the gain at the cutoff is `1 / (2 - 2 * resonance)`
+        (0, 0.75, cutoff, 2.0),
+        (0, 0.0, Sample::stereo(20.0, 40.0), 1.0),
+        (2, 0.0, Sample::stereo(20.0, 40.0), 0.0),
+    ];
+
+    for (output, resonance, probe, expected) in cases {
+        let params = [("Cutoff", cutoff), ("Resonance", Sample::mono(resonance))];
+        let cg = filter_graph(Svf, output, probe, &params);
+        assert_response(cg, probe, expected, 1e-3);
+    }
+}
+
+#[test]
+fn ladder_response() {
+    let cutoff = Sample::stereo(1000.0, 2000.0);
+
+    // each pole is 3 dB down and 45 degrees behind at the cutoff, so the feedback is negative
+    // there, while the pass band drops by `1 / (1 + 4 * resonance)`
+    let cases = [
+        (0.0, cutoff, 0.25),
+        (0.5, cutoff, 0.5),
+        (0.5, Sample::stereo(10.0, 20.0), 1.0 / 3.0),
+        // 24 dB per octave, so three octaves up is over 70 dB down
+        (0.0, cutoff * 8.0, 0.0),
+    ];
+
+    for (resonance, probe, expected) in cases {
+        let params = [("Cutoff", cutoff), ("Resonance", Sample::mono(resonance))];
+        let cg = filter_graph(Ladder, 0, probe, &params);
+        assert_response(cg, probe, expected, 1e-3);
+    }
+}
+
+#[test]
+fn filters_under_audio_rate_modulation() {
+    // resonant filters whose cutoff is swept from 0 Hz past Nyquist, 3000 times a second
+    let sweep = "node 1 Constant 110\nnode 2 Saw\nnode 3 Constant 3000\nnode 4 Sine\n\
+                 node 5 Constant 15000\nnode 6 Multiply\nnode 7 Constant 12000\nnode 8 Add\n\
+                 edge 1:0 -> 2:0\nedge 3:0 -> 4:0\nedge 4:0 -> 6:0\nedge 5:0 -> 6:1\n\
+                 edge 6:0 -> 8:0\nedge 7:0 -> 8:1\n";
+    // each filter is node 9, fed by the saw and swept by node 8
+    let filters = [
+        "node 9 Biquad Lowpass\nnode 10 Constant 10\nnode 11 Constant 0\n\
+         edge 8:0 -> 9:Frequency\nedge 10:0 -> 9:Q\nedge 11:0 -> 9:Gain\n",
+        "node 9 Biquad Peak\nnode 10 Constant 10\nnode 11 Constant 12\n\
+         edge 8:0 -> 9:Frequency\nedge 10:0 -> 9:Q\nedge 11:0 -> 9:Gain\n",
+        "node 9 Svf\nnode 10 Constant 0.99\n\
+         edge 8:0 -> 9:Cutoff\nedge 10:0 -> 9:Resonance\n",
+        "node 9 Ladder\nnode 10 Constant 0.99\n\
+         edge 8:0 -> 9:Cutoff\nedge 10:0 -> 9:Resonance\n",
+    ];
+
+    for filter in filters {
+        let mut cg = patch::parse(
+            48000,
+            &format!("{sweep}{filter}edge 2:0 -> 9:Input\nedge 9:0 -> aout\n"),
+        )
+        .unwrap();
+        let mut out = vec![Sample::default(); 48000];
+        cg.process_block(&mut out);
+
+        assert!(
+            out.iter()
+                .all(|s| s.l().abs() < 100.0 && s.r().abs() < 100.0),
+            "{filter}"
+        );
+    }
+}
+
+/// Runs `cg` for `len` samples with its gate held at `gate`, returning the outputs of node 2.
+fn envelope_samples(cg: &mut ControlGraph, gate: Sample, len: usize) -> Vec<(Sample, Sample)> {
+    cg.set_source(Source::Gate, gate);
+
+    (0..len)
+        .map(|_| {
+            cg.next_sample();
+            let vals = cg.try_get_node_vals(NodeIndex::new(2)).unwrap();
+            (vals[0], vals[1])
+        })
+        .collect()
+}
+
+#[test]
+fn adsr() {
+    // at 1000 Hz, each stage is 10 samples long
+    let adsr = |args: &str| {
+        let src = format!(
+            "node 1 Gate\nnode 2 Adsr {args}\nnode 3 Constant 0.01\nnode 4 Constant 0.5\n\
+             edge 1:0 -> 2:Gate\nedge 3:0 -> 2:Attack\nedge 3:0 -> 2:Decay\n\
+             edge 4:0 -> 2:Sustain\nedge 3:0 -> 2:Release\nedge 2:Output -> aout\n"
+        );
+        patch::parse(1000, &src).unwrap()
+    };
+    let levels = |samples: &[(Sample, Sample)]| samples.iter().map(|s| s.0.l()).collect::<Vec<_>>();
+
+    let mut cg = adsr("Linear Retrigger");
+    assert!(envelope_samples(&mut cg, Sample::mono(0.0), 5)
+        .iter()
+        .all(|&(level, end)| level == Sample::mono(0.0) && end == Sample::mono(0.0)));
+
+    // attack, decay, then sustain
+    let held = levels(&envelope_samples(&mut cg, Sample::mono(1.0), 30));
+    let expected = (1..=10)
+        .map(|i| i as f64 / 10.0)
+        .chain((1..=10).map(|i| 1.0 - 0.5 * i as f64 / 10.0))
+        .chain([0.5; 10]);
+    assert!(
+        held.iter().zip(expected).all(|(&a, b)| common::close(a, b)),
+        "{held:?}"
+    );
+
+    // release, which ends on its last sample
+    let released = envelope_samples(&mut cg, Sample::mono(0.0), 12);
+    assert!(levels(&released)
+        .iter()
+        .zip(
+            (1..=10)
+                .map(|i| 0.5 - 0.5 * i as f64 / 10.0)
+                .chain([0.0; 2])
+        )
+        .all(|(&a, b)| common::close(a, b)));
+    let ends = released.iter().map(|s| s.1.l()).collect::<Vec<_>>();
+    assert_eq!(
+        ends,
+        [0.0; 9]
+            .into_iter()
+            .chain([1.0, 0.0, 0.0])
+            .collect::<Vec<_>>()
+    );
+
+    // releasing during the attack starts the release from where the attack got to
+    let mut cg = adsr("Linear Retrigger");
+    envelope_samples(&mut cg, Sample::mono(1.0), 4);
+    let released = levels(&envelope_samples(&mut cg, Sample::mono(0.0), 1));
+    assert!(common::close(released[0], 0.4 - 0.04), "{released:?}");
+
+    // retriggering restarts from 0, while legato carries on from the current level
+    for (trigger, expected) in [("Retrigger", 0.1), ("Legato", 0.325)] {
+        let mut cg = adsr(&format!("Linear {trigger}"));
+        envelope_samples(&mut cg, Sample::mono(1.0), 30);
+        envelope_samples(&mut cg, Sample::mono(0.0), 5);
+        let retriggered = levels(&envelope_samples(&mut cg, Sample::mono(1.0), 1));
+        assert!(
+            common::close(retriggered[0], expected),
+            "{trigger}: {retriggered:?}"
+        );
+    }
+
+    // exponential stages move faster at first, but land on the same levels
+    let mut cg = adsr("Exponential Retrigger");
+    let held = levels(&envelope_samples(&mut cg, Sample::mono(1.0), 30));
+    assert!(held[..10].windows(2).all(|w| w[0] < w[1]));
+    assert!(held[2] > 0.3 && common::close(held[9], 1.0) && common::close(held[29], 0.5));
+
+    // each channel has its own gate
+    let mut cg = adsr("Linear Retrigger");
+    let stereo = envelope_samples(&mut cg, Sample::stereo(1.0, 0.0), 10);
+    assert!(stereo.iter().all(|s| s.0.r() == 0.0) && common::close(stereo[9].0.l(), 1.0));
+
+    // unconnected times are 0 and an unconnected sustain is 1
+    let src = "node 1 Gate\nnode 2 Adsr Linear Retrigger\n\
+               edge 1:0 -> 2:Gate\nedge 2:Output -> aout\n";
+    let mut cg = patch::parse(1000, src).unwrap();
+    assert_eq!(
+        levels(&envelope_samples(&mut cg, Sample::mono(1.0), 3)),
+        [1.0; 3]
+    );
+    let released = envelope_samples(&mut cg, Sample::mono(0.0), 1);
+    assert_eq!(released[0], (Sample::mono(0.0), Sample::mono(1.0)));
+}
+
+#[test]
+fn multi_stage_envelope() {
+    // up to 1 over 10 samples, down to 0.2 over 20 and back to 0 over 5, sustaining at stage 1.
+    // Each stage's time and level are ports `1 + 2 * i` and `2 + 2 * i`
+    let envelope = |sustain: &str| {
+        let src = format!(
+            "node 1 Gate\nnode 2 MultiStageEnvelope 3 {sustain} Linear Retrigger\n\
+             node 3 Constant 0.01\nnode 4 Constant 1\nnode 5 Constant 0.02\n\
+             node 6 Constant 0.2\nnode 7 Constant 0.005\nnode 8 Constant 0\n\
+             edge 1:0 -> 2:Gate\nedge 3:0 -> 2:1\nedge 4:0 -> 2:2\nedge 5:0 -> 2:3\n\
+             edge 6:0 -> 2:4\nedge 7:0 -> 2:5\nedge 8:0 -> 2:6\nedge 2:Output -> aout\n"
+        );
+        patch::parse(1000, &src).unwrap()
+    };
+
+    // without a sustain stage, a single sample of gate runs through every stage
+    let mut cg = envelope("none");
+    let mut samples = envelope_samples(&mut cg, Sample::mono(1.0), 1);
+    samples.extend(envelope_samples(&mut cg, Sample::mono(0.0), 40));
+    assert!(common::close(samples[9].0.l(), 1.0) && common::close(samples[29].0.l(), 0.2));
+    assert!(common::close(samples[34].0.l(), 0.0));
+    let ends = samples
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.1.l() == 1.0)
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    assert_eq!(ends, [34]);
+
+    // with one, the level holds until the gate closes
+    let mut cg = envelope("1");
+    let held = envelope_samples(&mut cg, Sample::mono(1.0), 100);
+    assert!(common::close(held[99].0.l(), 0.2));
+    let released = envelope_samples(&mut cg, Sample::mono(0.0), 5);
+    assert!(common::close(released[4].0.l(), 0.0) && released[4].1.l() == 1.0);
+
+    assert_eq!(
+        cg.get_node(NodeIndex::new(2)).get_args(),
+        ["3", "1", "Linear", "Retrigger"]
+    );
+    assert_eq!(
+        patch::parse(1000, "node 1 MultiStageEnvelope 2 2 Linear Legato")
+            .unwrap_err()
+            .message,
+        "sustain stage 2 is past the last of 2 stages (stages go from 0 to 1)"
+    );
+}
+
+#[test]
+fn multi_stage_envelope_errors() {
+    assert_eq!(
+        MultiStageEnvelope::new(2, Some(2), Curve::Linear, Trigger::Legato).unwrap_err(),
+        EnvelopeError::SustainPastEnd {
+            sustain: 2,
+            stages: 2
+        }
+    );
+    assert_eq!(
+        MultiStageEnvelope::new(0, None, Curve::Linear, Trigger::Legato).unwrap_err(),
+        EnvelopeError::NoStages
+    );
+
+    // deserializing goes through `new`, and rebuilds the labels rather than trusting them
+    #[derive(serde::Serialize)]
+    struct Fields {
+        stages: usize,
+        sustain: Option<usize>,
+        curve: Curve,
+        trigger: Trigger,
+        labels: Vec<String>,
+    }
+    let data = |sustain| {
+        postcard::to_stdvec(&Fields {
+            stages: 3,
+            sustain,
+            curve: Curve::Linear,
+            trigger: Trigger::Legato,
+            labels: vec!["Gate".into()],
+        })
+        .unwrap()
+    };
+
+    let envelope: MultiStageEnvelope = postcard::from_bytes(&data(Some(1))).unwrap();
+    assert_eq!(envelope.get_input_labels().len(), 7);
+    assert!(postcard::from_bytes::<MultiStageEnvelope>(&data(Some(3))).is_err());
+}
+
+#[test]
+fn lfo() {
+    // at 1000 Hz, a 10 Hz cycle is 100 samples long
+    let lfo = |args: &str, phase: f64| {
+        let src = format!(
+            "node 1 Constant 10\nnode 2 Constant {phase}\nnode 3 Lfo {args}\n\
+             edge 1:0 -> 3:Rate\nedge 2:0 -> 3:Phase\nedge 3:0 -> aout\n"
+        );
+        let mut cg = patch::parse(1000, &src).unwrap();
+        (0..400).map(|_| cg.next_sample().l()).collect::<Vec<_>>()
+    };
+
+    // (shape, values at each quarter of the cycle)
+    let shapes = [
+        ("Sine", [0.0, 1.0, 0.0, -1.0]),
+        ("Triangle", [0.0, 1.0, 0.0, -1.0]),
+        ("Saw", [-1.0, -0.5, 0.0, 0.5]),
+        ("Square", [1.0, 1.0, -1.0, -1.0]),
+    ];
+    for (shape, quarters) in shapes {
+        let samples = lfo(&format!("{shape} Bipolar"), 0.0);
+        for (i, expected) in quarters.into_iter().enumerate() {
+            assert!(
+                common::close(samples[100 + i * 25], expected),
+                "{shape} {i}"
+            );
+        }
+
+        // unipolar maps the same shape into 0..1, and the phase shifts it
+        let unipolar = lfo(&format!("{shape} Unipolar"), 0.25);
+        for (i, expected) in quarters.into_iter().cycle().skip(1).take(4).enumerate() {
+            assert!(
+                common::close(unipolar[100 + i * 25], (expected + 1.0) / 2.0),
+                "{shape} {i}"
+            );
+        }
+    }
+
+    // a new value for each cycle, which the smooth shape glides between
+    let held = lfo("SampleAndHold Bipolar", 0.0);
+    let smooth = lfo("SmoothRandom Bipolar", 0.0);
+    for cycle in held.chunks(100) {
+        assert!(cycle
+            .iter()
+            .all(|&s| s == cycle[0] && (-1.0..=1.0).contains(&s)));
+    }
+    assert!(held
+        .chunks(100)
+        .zip(held.chunks(100).skip(1))
+        .all(|(a, b)| a[0] != b[0]));
+    for i in 1..4 {
+        assert!(common::close(smooth[i * 100], held[(i - 1) * 100]));
+    }
+    assert!(smooth.windows(2).all(|w| (w[1] - w[0]).abs() < 0.1));
+
+    // an unconnected phase is 0, and an unconnected rate holds the Lfo still
+    let unconnected = |src: &str| {
+        let mut cg = patch::parse(1000, src).unwrap();
+        (0..400).map(|_| cg.next_sample().l()).collect::<Vec<_>>()
+    };
+    assert_eq!(
+        unconnected(
+            "node 1 Constant 10\nnode 2 Lfo Saw Bipolar\nedge 1:0 -> 2:Rate\nedge 2:0 -> aout\n"
+        ),
+        lfo("Saw Bipolar", 0.0)
+    );
+    assert!(unconnected("node 1 Lfo Saw Bipolar\nedge 1:0 -> aout\n")
+        .iter()
+        .all(|&s| s == -1.0));
+}
+
+#[test]
+fn lfo_tempo_sync() {
+    // 2 cycles per beat at 120 BPM is 4 Hz
+    let synced = "node 1 Constant 2\nnode 2 Tempo\nnode 3 Constant 0\nnode 4 Lfo Saw Bipolar\n\
+                  edge 1:0 -> 4:Rate\nedge 2:0 -> 4:Tempo\nedge 3:0 -> 4:Phase\nedge 4:0 -> aout\n";
+    let free = "node 1 Constant 4\nnode 2 Constant 0\nnode 3 Lfo Saw Bipolar\n\
+                edge 1:0 -> 3:Rate\nedge 2:0 -> 3:Phase\nedge 3:0 -> aout\n";
+
+    let mut synced = patch::parse(48000, synced).unwrap();
+    let mut free = patch::parse(48000, free).unwrap();
+    synced.set_source(Source::Tempo, Sample::mono(120.0));
+
+    let mut expected = [Sample::default(); 1024];
+    let mut actual = [Sample::default(); 1024];
+    free.process_block(&mut expected);
+    synced.process_block(&mut actual);
+    assert_eq!(actual, expected);
+
+    // doubling the tempo doubles the rate
+    synced.set_source(Source::Tempo, Sample::mono(240.0));
+    let before = synced.next_sample().l();
+    let after = synced.next_sample().l();
+    assert!(((after - before) - 2.0 * 8.0 / 48000.0).abs() < 1e-9);
+
+    // without a positive tempo, the rate stays in Hz, as it does while `Tempo` is unconnected
+    let unsynced = "node 1 Constant 4\nnode 2 Tempo\nnode 3 Constant 0\nnode 4 Lfo Saw Bipolar\n\
+                    edge 1:0 -> 4:Rate\nedge 2:0 -> 4:Tempo\nedge 3:0 -> 4:Phase\nedge 4:0 -> aout\n";
+    for tempo in [0.0, -120.0, f64::NAN] {
+        let mut unsynced = patch::parse(48000, unsynced).unwrap();
+        unsynced.set_source(Source::Tempo, Sample::mono(tempo));
+        unsynced.process_block(&mut actual);
+        assert_eq!(actual, expected, "{tempo}");
+    }
+    assert!(common::close(
+        expected[1].l() - expected[0].l(),
+        2.0 * 4.0 / 48000.0
+    ));
+}
+
+#[test]
+fn delay() {
+    // at 1000 Hz, 5 ms is 5 samples
+    let text = "node 1 Constant 1\nnode 2 Constant 0.005\nnode 3 Delay 0.01\n\
+                edge 1:0 -> 3:Input\nedge 2:0 -> 3:Time\nedge 3:0 -> aout\n";
+    let mut cg = patch::parse(1000, text).unwrap();
+    assert_eq!(
+        patch::write(&cg).unwrap().lines().nth(2),
+        Some("node 3 Delay 0.01")
+    );
+
+    let step = |cg: &mut ControlGraph, len: usize| {
+        (0..len).map(|_| cg.next_sample().l()).collect::<Vec<_>>()
+    };
+    let expected = |delay: usize| [vec![0.0; delay], vec![1.0; 3]].concat();
+    assert_eq!(step(&mut cg, 8), expected(5));
+
+    // the buffer is resized and cleared, so the same time is twice as many samples
+    cg.set_sample_rate(2000);
+    assert_eq!(step(&mut cg, 13), expected(10));
+
+    // times past the maximum are shortened to it
+    let text = text.replace("Constant 0.005", "Constant 1");
+    let mut cg = patch::parse(1000, &text).unwrap();
+    assert_eq!(step(&mut cg, 13), expected(10));
+
+    assert_eq!(
+        patch::parse(1000, "node 1 Delay -1").unwrap_err().message,
+        "delay times must be finite and at least 0"
+    );
+}
+
+#[test]
+fn fractional_delay() {
+    // a 10 Hz sine at 1000 Hz, delayed by 2.5 samples
+    let delayed = |interpolation: &str| {
+        let text = format!(
+            "node 1 Constant 10\nnode 2 Constant 0\nnode 3 Lfo Sine Bipolar\n\
+             node 4 Constant 0.0025\nnode 5 FractionalDelay 0.01 {interpolation}\n\
+             edge 1:0 -> 3:Rate\nedge 2:0 -> 3:Phase\nedge 3:0 -> 5:Input\nedge 4:0 -> 5:Time\n\
+             edge 5:0 -> aout\n"
+        );
+        let mut cg = patch::parse(1000, &text).unwrap();
+        assert_eq!(
+            patch::write(&cg).unwrap().lines().nth(4),
+            Some(format!("node 5 FractionalDelay 0.01 {interpolation}").as_str())
+        );
+
+        (0..200).map(|_| cg.next_sample().l()).collect::<Vec<_>>()
+    };
+    let expected = |n: usize| ((n as f64 - 2.5) * 0.01 * std::f64::consts::TAU).sin();
+
+    for (interpolation, tolerance) in [("Linear", 1e-3), ("Cubic", 1e-5), ("Allpass", 1e-4)] {
+        let samples = delayed(interpolation);
+
+        // skips the allpass filter's transient
+        for (n, s) in samples.iter().enumerate().skip(100) {
+            assert!((s - expected(n)).abs() < tolerance, "{interpolation} {n}");
+        }
+    }
+}
+
+#[test]
+fn chorus_and_flanger() {
+    let effect = |name: &str, mix: f64| {
+        let mut dry = preset(48000, |cg| {
+            let sine = cg.connect_const_new(440.0, Sine);
+            cg.connect_ex_aout(sine);
+        });
+        let mut wet = preset(48000, |cg| {
+            let sine = cg.connect_const_new(440.0, Sine);
+            let (inputs, outputs) = match name {
+                "Chorus" => cg.insert_container(Chorus),
+                _ => cg.insert_container(Flanger),
+            };
+            cg.connect_ex_ex(sine, inputs.named("Input"));
+            cg.connect_const_ex(2.0, inputs.named("Rate"));
+            cg.connect_const_ex(1.0, inputs.named("Depth"));
+            cg.connect_const_ex(mix, inputs.named("Mix"));
+            cg.connect_ex_aout(outputs.named("Output"));
+        });
+
+        let len = 48000;
+        let dry = (0..len).map(|_| dry.next_sample()).collect::<Vec<_>>();
+        let wet = (0..len).map(|_| wet.next_sample()).collect::<Vec<_>>();
+        (dry, wet)
+    };
+
+    for name in ["Chorus", "Flanger"] {
+        let (dry, wet) = effect(name, 0.0);
+        assert_eq!(dry, wet, "{name}");
+
+        // the sweep reaches both channels, but a quarter of a cycle apart
+        let (dry, wet) = effect(name, 0.5);
+        assert!(wet
+            .iter()
+            .all(|s| s.l().abs() <= 1.0 + 1e-9 && s.r().abs() <= 1.0 + 1e-9));
+        assert!(dry
+            .iter()
+            .zip(&wet)
+            .any(|(d, w)| (d.l() - w.l()).abs() > 0.1));
+        assert!(wet.iter().any(|s| (s.l() - s.r()).abs() > 0.1));
+    }
+}
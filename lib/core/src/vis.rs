@@ -15,11 +15,13 @@ pub fn visualize_graph(cg: &ControlGraph) -> String {
 
         let node = cg.get_node(node_index);
         let ident = node.get_ident();
+        let output_labels = node.get_output_labels();
 
         let root_label = format!(
             "{}{}",
             match ident {
                 "ContainerInput" | "ContainerOutput" => "",
+                _ if output_labels.len() > 1 => "",
                 _ => "<o>",
             },
             match ident {
@@ -55,6 +57,19 @@ pub fn visualize_graph(cg: &ControlGraph) -> String {
             root_label
         };
 
+        // nodes with several outputs get a port for each one
+        let fmt = if output_labels.len() > 1 {
+            let mut output_labels_with_ids = vec![];
+
+            for (label_num, output_label) in output_labels.iter().enumerate() {
+                output_labels_with_ids.push(format!("<o{}>{}", label_num, output_label));
+            }
+
+            format!("{fmt}|{{{}}}", output_labels_with_ids.join("|"))
+        } else {
+            fmt
+        };
+
         if ident == "ContainerInput" {
             src.push(format!(
                 "s{node_num} [label = \"{{{fmt}}}\";style=filled;color=deepskyblue;];"
@@ -72,6 +87,28 @@ pub fn visualize_graph(cg: &ControlGraph) -> String {
 
     let (_, rendered) = subgraph(cg, 0);
 
+    // feedback isn't an edge of the DAG, so it's drawn separately
+    let node_indexes = cg.get_node_indexes().collect::<Vec<_>>();
+    let node_num = |node_index| node_indexes.iter().position(|&i| i == node_index).unwrap();
+    let feedback = cg
+        .get_feedback_edges()
+        .iter()
+        .map(|f| {
+            let o = if cg.get_node(f.src).get_output_labels().len() > 1 {
+                format!("o{}", f.src_port)
+            } else {
+                "o".to_string()
+            };
+
+            format!(
+                "s{}:{o} -> s{}:w [style = dashed;constraint = false;];",
+                node_num(f.src),
+                node_num(f.tap)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
     format!(
         r#"digraph structs {{
         node [shape = record;];
@@ -82,6 +119,7 @@ pub fn visualize_graph(cg: &ControlGraph) -> String {
         aout;
         {}
         {rendered}
+        {feedback}
     }}"#,
         src.join("\n"),
     )
@@ -134,12 +172,21 @@ fn subgraph(cg: &ControlGraph, i: usize) -> (SubgraphCalc, String) {
         let node_ident = node.get_ident();
         let children = cg.get_node_neighbors(node_index, Outgoing);
 
+        let multi_output = node.get_output_labels().len() > 1;
+
         for Neighbor {
+            src_port_id: src_edge,
             dest_port_id: child_edge,
             node_index: child_node_index,
             ..
         } in children
         {
+            let o = if multi_output {
+                format!("o{src_edge}")
+            } else {
+                "o".to_string()
+            };
+
             if !(container_node_indexes.contains(&child_node_index)
                 || i == 0 && child_node_index == NodeIndex::new(0))
             {
@@ -165,17 +212,17 @@ fn subgraph(cg: &ControlGraph, i: usize) -> (SubgraphCalc, String) {
                         {
                             format!("s{node_num}:e -> aout;")
                         } else if child_node_id == 0 {
-                            format!("s{node_num}:o -> aout;")
+                            format!("s{node_num}:{o} -> aout;")
                         } else if node_ident == "ContainerOutput" && ident == "ContainerInput" {
                             format!("s{node_num}:e -> s{child_node_id}:w;")
                         } else if ident == "ContainerInput" || ident == "ContainerOutput" {
-                            format!("s{node_num}:o -> s{child_node_id}:w;")
+                            format!("s{node_num}:{o} -> s{child_node_id}:w;")
                         } else if node_ident == "ContainerInput" || node_ident == "ContainerOutput"
                         {
                             //rustfmt what are u doing to that opening bracket
                             format!("s{node_num}:e -> s{child_node_id}:i{child_edge};")
                         } else {
-                            format!("s{node_num}:o -> s{child_node_id}:i{child_edge};")
+                            format!("s{node_num}:{o} -> s{child_node_id}:i{child_edge};")
                         }
                     }
                     None => String::new(),
@@ -0,0 +1,101 @@
+//! Shares the control graph between the editor, which owns the copy that's edited, and the
+//! audio thread, which plays compiled copies of it.
+
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{RwLock, RwLockReadGuard, TryLockError};
+
+use dagrid_core::control::ControlGraph;
+use dagrid_core::handoff::Handoff;
+use nih_plug::prelude::*;
+
+use crate::voice::{Voices, MAX_VOICES};
+
+/// Copies of the control graph with their caches built, so that the audio thread can play them
+/// without allocating.
+pub(crate) struct Compiled {
+    /// Played when MIDI is off.
+    pub main: ControlGraph,
+    /// Played by each voice.
+    pub voices: Vec<ControlGraph>,
+}
+
+pub(crate) struct SharedGraph {
+    /// The copy that's edited. The audio thread never locks it.
+    graph: RwLock<ControlGraph>,
+    handoff: Handoff<Compiled>,
+    /// The longest block that compiled graphs are prepared for.
+    max_block_len: AtomicUsize,
+}
+
+impl SharedGraph {
+    pub fn new(cg: ControlGraph) -> Self {
+        Self {
+            graph: RwLock::new(cg),
+            handoff: Handoff::new(),
+            max_block_len: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, ControlGraph> {
+        self.graph.read().unwrap()
+    }
+
+    /// Returns `None` while the graph is being edited.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, ControlGraph>> {
+        match self.graph.try_read() {
+            Ok(cg) => Some(cg),
+            Err(TryLockError::WouldBlock) => None,
+            Err(TryLockError::Poisoned(e)) => panic!("{e}"),
+        }
+    }
+
+    /// Edits the graph with `f`, then compiles it and publishes it to the audio thread.
+    pub fn edit<R>(&self, f: impl FnOnce(&mut ControlGraph) -> R) -> R {
+        let mut cg = self.graph.write().unwrap();
+        let result = f(&mut cg);
+
+        match self.compile(&cg) {
+            Ok(compiled) => self.handoff.publish(compiled),
+            Err(e) => nih_error!("couldn't copy the edited control graph: {e}"),
+        }
+
+        result
+    }
+
+    pub fn set_max_block_len(&self, max_block_len: usize) {
+        self.max_block_len.store(max_block_len, Ordering::Relaxed);
+    }
+
+    /// Swaps in the graphs published by the last edit, carrying over the state of nodes that are
+    /// in both. Never blocks, allocates or frees memory.
+    pub fn receive(&self, main: &mut ControlGraph, voices: &mut Voices) -> bool {
+        self.handoff.receive(|compiled| {
+            compiled.main.carry_state_from(main);
+            std::mem::swap(main, &mut compiled.main);
+            voices.swap_graphs(&mut compiled.voices);
+        })
+    }
+
+    /// Drops the graphs replaced by the last [receive](SharedGraph::receive). This also happens
+    /// on every edit.
+    pub fn collect(&self) {
+        self.handoff.collect();
+    }
+
+    fn compile(&self, cg: &ControlGraph) -> Result<Compiled, Box<dyn Error>> {
+        let max_block_len = self.max_block_len.load(Ordering::Relaxed);
+        let copy = || {
+            cg.try_clone().map(|mut copy| {
+                // incomplete graphs output silence until they're connected to `aout`
+                let _ = copy.try_prepare(max_block_len);
+                copy
+            })
+        };
+
+        Ok(Compiled {
+            main: copy()?,
+            voices: (0..MAX_VOICES).map(|_| copy()).collect::<Result<_, _>>()?,
+        })
+    }
+}
@@ -1,6 +1,5 @@
 use std::sync::{Arc, RwLock};
 
-use dagrid_core::control::ControlGraph;
 use nih_plug::context::gui::GuiContext;
 use nih_plug_vello::vello::kurbo::*;
 use nih_plug_vello::vello::peniko::*;
@@ -8,16 +7,18 @@ use nih_plug_vello::vello::Scene;
 use nih_plug_vello::SimpleText;
 use nih_plug_vello::VelloContext;
 
+use crate::graph::SharedGraph;
+
 pub(crate) struct Ctx {
-    control_graph: Arc<RwLock<ControlGraph>>,
+    graph: Arc<SharedGraph>,
     time: std::time::SystemTime,
     text: SimpleText,
 }
 
 impl Ctx {
-    pub fn new(control_graph: Arc<RwLock<ControlGraph>>) -> Self {
+    pub fn new(graph: Arc<SharedGraph>) -> Self {
         Self {
-            control_graph,
+            graph,
             time: std::time::SystemTime::now(),
             text: SimpleText::new(),
         }
@@ -31,7 +32,9 @@ pub(crate) fn draw(
     let ctx = &mut ctx.write().unwrap().user;
     let time = ctx.time.elapsed().unwrap().as_secs_f64();
 
-    let cg = ctx.control_graph.write().unwrap();
+    // graphs retired by the audio thread are dropped here, rather than waiting for the next edit
+    ctx.graph.collect();
+    let cg = ctx.graph.read();
 
     let idxs = cg.get_node_indexes();
 
@@ -46,16 +49,17 @@ pub(crate) fn draw(
         &rect,
     );
 
+    // the editor's copy of the graph is never processed, so its node values are all NaN, and
+    // nodes are colored by their position instead
     for (i, id) in idxs.enumerate() {
         let text = &format!("{}", cg.get_node(id).get_ident());
-        let val = cg.get_node_val(id);
         ctx.text.add(
             scene,
             None,
             text_size as f32,
             Some(&Brush::Solid(Color::hlc(
-                (val.r() + 1.0) * 180.0,
-                (val.l() + 1.0) * 50.0,
+                i as f64 * 37.0 % 360.0,
+                50.0,
                 127.0,
             ))),
             Affine::translate((200.0, 50.0 + text_size * 1.5 * i as f64))
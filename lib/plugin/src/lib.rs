@@ -1,6 +1,13 @@
+mod graph;
 mod gui;
 mod params;
 mod plug;
+mod state;
+mod voice;
+
+#[cfg(test)]
+mod tests;
 
 pub use params::DaGridParams;
 pub use plug::DaGrid;
+pub use voice::VoiceStealing;
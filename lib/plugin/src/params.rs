@@ -1,19 +1,78 @@
+use std::sync::Arc;
+
+use dagrid_core::node::MACRO_COUNT;
 use nih_plug::prelude::*;
 
+use crate::graph::SharedGraph;
+use crate::state::PersistedPatch;
+use crate::voice::{VoiceStealing, MAX_VOICES};
+
 #[derive(Params)]
 pub struct DaGridParams {
     #[id = "gain"]
     pub gain: FloatParam,
 
-    #[id = "freq"]
-    pub frequency: FloatParam,
-
     #[id = "usemid"]
     pub use_midi: BoolParam,
+
+    #[id = "voices"]
+    pub voices: IntParam,
+
+    #[id = "steal"]
+    pub voice_stealing: EnumParam<VoiceStealing>,
+
+    #[nested(array, group = "Macros")]
+    pub macros: [MacroParams; MACRO_COUNT],
+
+    #[persist = "patch"]
+    pub patch: PersistedPatch,
+}
+
+/// A host parameter that's read by the graph's `ParamSource` nodes. Host parameters can't be
+/// renamed, so the name, range and unit are saved with the graph instead, and only used to
+/// display the value.
+#[derive(Params)]
+pub struct MacroParams {
+    #[id = "macro"]
+    pub value: FloatParam,
+}
+
+impl MacroParams {
+    fn new(n: usize, graph: &Arc<SharedGraph>) -> Self {
+        let shared = graph.clone();
+        let value_to_string = Arc::new(move |value: f32| match shared.try_read() {
+            Some(cg) => {
+                let desc = cg.get_macro(n);
+                format!("{:.2}{}", desc.map(value as f64), desc.unit)
+            }
+            None => format!("{value:.2}"),
+        });
+
+        let shared = graph.clone();
+        let string_to_value = Arc::new(move |string: &str| {
+            let cg = shared.try_read()?;
+            let desc = cg.get_macro(n);
+            let value = string.trim().trim_end_matches(desc.unit.trim()).trim();
+
+            value.parse().ok().map(|v| desc.unmap(v) as f32)
+        });
+
+        Self {
+            value: FloatParam::new(
+                format!("Macro {}", n + 1),
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(10.0))
+            .with_value_to_string(value_to_string)
+            .with_string_to_value(string_to_value),
+        }
+    }
 }
 
-impl Default for DaGridParams {
-    fn default() -> Self {
+impl DaGridParams {
+    /// Creates the parameters, whose macros are displayed using the descriptions in `graph`.
+    pub(crate) fn new(graph: &Arc<SharedGraph>) -> Self {
         Self {
             gain: FloatParam::new(
                 "Gain",
@@ -26,21 +85,18 @@ impl Default for DaGridParams {
             .with_smoother(SmoothingStyle::Linear(3.0))
             .with_step_size(0.01)
             .with_unit(" dB"),
-            frequency: FloatParam::new(
-                "Frequency",
-                420.0,
-                FloatRange::Skewed {
-                    min: 1.0,
-                    max: 20_000.0,
-                    factor: FloatRange::skew_factor(-2.0),
-                },
-            )
-            .with_smoother(SmoothingStyle::Linear(10.0))
-            // We purposely don't specify a step size here, but the parameter should still be
-            // displayed as if it were rounded. This formatter also includes the unit.
-            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
-            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
             use_midi: BoolParam::new("Use MIDI", false),
+            voices: IntParam::new(
+                "Voices",
+                8,
+                IntRange::Linear {
+                    min: 1,
+                    max: MAX_VOICES as i32,
+                },
+            ),
+            voice_stealing: EnumParam::new("Voice Stealing", VoiceStealing::Oldest),
+            macros: std::array::from_fn(|n| MacroParams::new(n, graph)),
+            patch: PersistedPatch::new(graph),
         }
     }
 }
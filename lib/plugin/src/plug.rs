@@ -1,51 +1,47 @@
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 
 use dagrid_core::{
+    node::Source,
     presets::{self, preset},
     Sample,
 };
 use nih_plug::prelude::*;
 use nih_plug_vello::create_vello_editor;
 
-use crate::{gui::Ctx, params::DaGridParams};
+use crate::{graph::SharedGraph, gui::Ctx, params::DaGridParams, voice::Voices};
 use dagrid_core::control::ControlGraph;
 
+/// The longest chunk rendered while a macro is being smoothed.
+const MACRO_STEP: usize = 32;
+
 pub struct DaGrid {
     params: Arc<DaGridParams>,
     sample_rate: f32,
 
-    control_graph: Arc<RwLock<ControlGraph>>,
-
-    /// The MIDI note ID of the active note, if triggered by MIDI.
-    midi_note_id: u8,
-    /// The frequency if the active note, if triggered by MIDI.
-    midi_note_freq: f32,
-    /// A simple attack and release envelope to avoid clicks. Controlled through velocity and
-    /// aftertouch.
-    ///
-    /// Smoothing is built into the parameters, but you can also use them manually if you need to
-    /// smooth soemthing that isn't a parameter.
-    midi_note_gain: Smoother<f32>,
-}
+    /// The graph that's edited, and published to the audio thread.
+    shared: Arc<SharedGraph>,
+    /// The audio thread's copy of the graph, played when MIDI is off.
+    graph: ControlGraph,
+    /// Scratch buffer that the control graph renders each block into.
+    block: Vec<Sample>,
 
-impl DaGrid {
-    fn eval_control_graph(&mut self, _frequency: f32) -> Sample {
-        self.control_graph.write().unwrap().next_sample()
-    }
+    /// Copies of the control graph that play MIDI notes.
+    voices: Voices,
 }
 
 impl Default for DaGrid {
     fn default() -> Self {
         let cg = preset(0, presets::subsynth_with_containers);
+        let shared = Arc::new(SharedGraph::new(cg));
 
         Self {
-            params: Arc::new(DaGridParams::default()),
+            params: Arc::new(DaGridParams::new(&shared)),
             sample_rate: 1.0,
-            control_graph: Arc::new(RwLock::new(cg)),
+            shared,
+            graph: ControlGraph::new(0),
+            block: vec![],
 
-            midi_note_id: 0,
-            midi_note_freq: 1.0,
-            midi_note_gain: Smoother::new(SmoothingStyle::Linear(5.0)),
+            voices: Voices::default(),
         }
     }
 }
@@ -72,7 +68,7 @@ impl Plugin for DaGrid {
         // },
     ];
 
-    const MIDI_INPUT: MidiConfig = MidiConfig::Basic;
+    const MIDI_INPUT: MidiConfig = MidiConfig::MidiCCs;
     const SAMPLE_ACCURATE_AUTOMATION: bool = true;
 
     type SysExMessage = ();
@@ -81,7 +77,7 @@ impl Plugin for DaGrid {
     fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
         create_vello_editor(
             nih_plug_vello::Size::new(512.0, 512.0),
-            Ctx::new(self.control_graph.clone()),
+            Ctx::new(self.shared.clone()),
             &crate::gui::draw,
         )
     }
@@ -96,18 +92,26 @@ impl Plugin for DaGrid {
         buffer_config: &BufferConfig,
         _context: &mut impl InitContext<Self>,
     ) -> bool {
-        self.control_graph
-            .write()
-            .unwrap()
-            .set_sample_rate(buffer_config.sample_rate as u32);
+        self.sample_rate = buffer_config.sample_rate;
+
+        let max_block_len = buffer_config.max_buffer_size as usize;
+        self.block = vec![Sample::default(); max_block_len];
+        self.voices.set_max_block_len(max_block_len);
+        self.shared.set_max_block_len(max_block_len);
 
-        true
+        self.shared
+            .edit(|cg| cg.set_sample_rate(buffer_config.sample_rate as u32));
+
+        // the graphs are needed before the first block, rather than whenever they arrive
+        self.shared.collect();
+        let received = self.shared.receive(&mut self.graph, &mut self.voices);
+        self.shared.collect();
+
+        received
     }
 
     fn reset(&mut self) {
-        self.midi_note_id = 0;
-        self.midi_note_freq = 1.0;
-        self.midi_note_gain.reset(0.0);
+        self.voices.reset();
     }
 
     fn process(
@@ -116,45 +120,116 @@ impl Plugin for DaGrid {
         _aux: &mut AuxiliaryBuffers,
         context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        let samples = buffer.samples();
+        let block = &mut self.block[..samples];
+
+        // This plugin can be either triggered by MIDI or controlled by a parameter
+        let use_midi = self.params.use_midi.value();
+        let count = self.params.voices.value() as usize;
+        let stealing = self.params.voice_stealing.value();
+
+        // picks up edits without waiting on the editor, which drops the graphs they replace
+        self.shared.receive(&mut self.graph, &mut self.voices);
+        let cg = &mut self.graph;
+
+        // tempo-synced nodes keep the last tempo if the host stops reporting it
+        if let Some(tempo) = context.transport().tempo {
+            let tempo = Sample::mono(tempo);
+            self.voices.set_source(Source::Tempo, tempo);
+            cg.set_source(Source::Tempo, tempo);
+        }
+
+        // render up to each event, so that the graph sees it on the right sample
+        let mut start = 0;
         let mut next_event = context.next_event();
-        for (sample_id, channel_samples) in buffer.iter_samples().enumerate() {
-            // Smoothing is optionally built into the parameters themselves
-            let gain = self.params.gain.smoothed.next();
+        while start < samples {
+            while let Some(event) = next_event {
+                if event.timing() as usize > start {
+                    break;
+                }
 
-            // This plugin can be either triggered by MIDI or controleld by a parameter
-            let out = if self.params.use_midi.value() {
-                // Act on the next MIDI event
-                while let Some(event) = next_event {
-                    if event.timing() > sample_id as u32 {
-                        break;
+                // events for the whole channel go to every voice, and to the graph itself
+                let mut set_source = |source, value: f32| {
+                    let value = Sample::mono(value as f64);
+                    self.voices.set_source(source, value);
+                    cg.set_source(source, value);
+                };
+
+                match event {
+                    NoteEvent::NoteOn {
+                        channel,
+                        note,
+                        velocity,
+                        ..
+                    } if use_midi => self.voices.note_on(
+                        count,
+                        stealing,
+                        self.sample_rate,
+                        (channel, note),
+                        velocity,
+                    ),
+                    NoteEvent::NoteOff { channel, note, .. } => {
+                        self.voices.note_off(self.sample_rate, (channel, note))
                     }
-
-                    match event {
-                        NoteEvent::NoteOn { note, velocity, .. } => {
-                            self.midi_note_id = note;
-                            self.midi_note_freq = util::midi_note_to_freq(note);
-                            self.midi_note_gain.set_target(self.sample_rate, velocity);
-                        }
-                        NoteEvent::NoteOff { note, .. } if note == self.midi_note_id => {
-                            self.midi_note_gain.set_target(self.sample_rate, 0.0);
-                        }
-                        NoteEvent::PolyPressure { note, pressure, .. }
-                            if note == self.midi_note_id =>
-                        {
-                            self.midi_note_gain.set_target(self.sample_rate, pressure);
-                        }
-                        _ => (),
+                    NoteEvent::PolyPressure {
+                        channel,
+                        note,
+                        pressure,
+                        ..
+                    } => self
+                        .voices
+                        .pressure(self.sample_rate, (channel, note), pressure),
+                    NoteEvent::MidiChannelPressure { pressure, .. } => {
+                        set_source(Source::Aftertouch, pressure)
                     }
-
-                    next_event = context.next_event();
+                    // centered at 0.5
+                    NoteEvent::MidiPitchBend { value, .. } => {
+                        set_source(Source::PitchBend, value * 2.0 - 1.0)
+                    }
+                    NoteEvent::MidiCC { cc, value, .. } => set_source(Source::Cc(cc), value),
+                    _ => (),
                 }
 
-                // This gain envelope prevents clicks with new notes and with released notes
-                self.eval_control_graph(self.midi_note_freq) * self.midi_note_gain.next() as f64
-            } else {
-                let frequency = self.params.frequency.smoothed.next();
-                self.eval_control_graph(frequency)
-            };
+                next_event = context.next_event();
+            }
+
+            let mut end = next_event.map_or(samples, |e| (e.timing() as usize).min(samples));
+
+            // sources hold one value for a whole chunk, so macros are smoothed in short steps
+            if self
+                .params
+                .macros
+                .iter()
+                .any(|m| m.value.smoothed.is_smoothing())
+            {
+                end = end.min(start + MACRO_STEP);
+            }
+
+            for (n, m) in self.params.macros.iter().enumerate() {
+                let normalized = m.value.smoothed.next_step((end - start) as u32);
+                let value = Sample::mono(cg.get_macro(n).map(normalized as f64));
+
+                self.voices.set_source(Source::Param(n as u8), value);
+                cg.set_source(Source::Param(n as u8), value);
+            }
+
+            let chunk = &mut block[start..end];
+
+            if use_midi {
+                chunk.fill(Sample::mono(0.0));
+                self.voices.render(count, chunk);
+            } else if cg.try_process_block(chunk).is_err() {
+                // output silence while the patch is incomplete
+                chunk.fill(Sample::mono(0.0));
+            }
+
+            start = end;
+        }
+
+        for (sample_id, channel_samples) in buffer.iter_samples().enumerate() {
+            // Smoothing is optionally built into the parameters themselves
+            let gain = self.params.gain.smoothed.next();
+            let out = self.block[sample_id];
 
             for (i, sample) in channel_samples.into_iter().enumerate() {
                 *sample = (if i == 0 { out.l() } else { out.r() }
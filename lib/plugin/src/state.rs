@@ -0,0 +1,72 @@
+//! Saves the control graph in the plugin's state, so that edits survive reopening a project.
+
+use std::sync::Arc;
+
+use dagrid_core::control::ControlGraph;
+use nih_plug::params::persist::PersistentField;
+use nih_plug::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::graph::SharedGraph;
+
+/// The version of [SavedPatch]. [ControlGraph::load] migrates older control graphs by itself, so
+/// this only changes along with the fields of [SavedPatch], which are migrated in
+/// [SavedPatch::load].
+pub const PATCH_VERSION: u32 = 1;
+
+/// A control graph saved by [ControlGraph::save], along with the version of its format.
+#[derive(Serialize, Deserialize)]
+pub struct SavedPatch {
+    version: u32,
+    data: Vec<u8>,
+}
+
+impl SavedPatch {
+    fn load(&self, sample_rate: u32) -> Result<ControlGraph, String> {
+        match self.version {
+            PATCH_VERSION => ControlGraph::load(sample_rate, &self.data).map_err(|e| e.to_string()),
+            v if v > PATCH_VERSION => Err(format!(
+                "it was saved by a newer version of the plugin (patch version {v})"
+            )),
+            v => Err(format!("patch version {v} is no longer supported")),
+        }
+    }
+}
+
+/// Persists the control graph shared with the editor and audio thread. It's saved whenever the
+/// host asks for the plugin's state, rather than after each edit.
+pub struct PersistedPatch(Arc<SharedGraph>);
+
+impl PersistedPatch {
+    pub(crate) fn new(graph: &Arc<SharedGraph>) -> Self {
+        Self(graph.clone())
+    }
+}
+
+impl<'a> PersistentField<'a, SavedPatch> for PersistedPatch {
+    /// Replaces the control graph, and publishes it to the audio thread.
+    fn set(&self, saved: SavedPatch) {
+        self.0.edit(|cg| match saved.load(cg.get_sample_rate()) {
+            Ok(loaded) => *cg = loaded,
+            Err(e) => {
+                nih_error!("couldn't restore the saved patch, so the current one is kept: {e}")
+            }
+        });
+    }
+
+    fn map<F, R>(&self, f: F) -> R
+    where
+        F: Fn(&SavedPatch) -> R,
+    {
+        let cg = self.0.read();
+        let data = cg.save().unwrap_or_else(|e| {
+            nih_error!("couldn't save the patch: {e}");
+            vec![]
+        });
+
+        f(&SavedPatch {
+            version: PATCH_VERSION,
+            data,
+        })
+    }
+}
@@ -0,0 +1,48 @@
+use dagrid_core::patch;
+use dagrid_core::Sample;
+
+use crate::voice::{VoiceStealing, Voices, MAX_VOICES};
+
+/// Renders `blocks` blocks of 64 samples from the first voice, and returns the peak level of each.
+fn render_peaks(voices: &mut Voices, blocks: usize) -> Vec<f64> {
+    let mut block = [Sample::mono(0.0); 64];
+
+    (0..blocks)
+        .map(|_| {
+            block.fill(Sample::mono(0.0));
+            voices.render(1, &mut block);
+            block.iter().map(|s| s.l().abs()).fold(0.0, f64::max)
+        })
+        .collect()
+}
+
+#[test]
+fn voice_release() {
+    // holds at 1, then releases over 100 ms, which is much longer than the gain's declicking
+    let text = "node 1 Gate\nnode 2 Adsr Linear Retrigger\nnode 3 Constant 0.001\n\
+                node 4 Constant 1\nnode 5 Constant 0.1\n\
+                edge 1:0 -> 2:Gate\nedge 3:0 -> 2:Attack\nedge 3:0 -> 2:Decay\n\
+                edge 4:0 -> 2:Sustain\nedge 5:0 -> 2:Release\nedge 2:Output -> aout\n";
+    let mut graphs = (0..MAX_VOICES)
+        .map(|_| patch::parse(48000, text).unwrap())
+        .collect::<Vec<_>>();
+
+    let mut voices = Voices::default();
+    voices.set_max_block_len(64);
+    voices.swap_graphs(&mut graphs);
+
+    voices.note_on(1, VoiceStealing::Oldest, 48000.0, (0, 60), 1.0);
+    let held = render_peaks(&mut voices, 10);
+    assert_eq!(held[9], 1.0);
+
+    // the release is heard in full, rather than being faded out along with the gain
+    voices.note_off(48000.0, (0, 60));
+    let released = render_peaks(&mut voices, 75);
+    for (i, peak) in released.iter().enumerate() {
+        let expected = 1.0 - (i * 64 + 1) as f64 / 4800.0;
+        assert!((peak - expected).abs() < 1e-9, "block {i}: {peak}");
+    }
+
+    // and the voice is silent once it's over
+    assert!(render_peaks(&mut voices, 50).iter().all(|&peak| peak == 0.0));
+}
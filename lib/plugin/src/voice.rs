@@ -0,0 +1,285 @@
+use dagrid_core::control::ControlGraph;
+use dagrid_core::node::Source;
+use dagrid_core::Sample;
+use nih_plug::prelude::*;
+
+/// The most voices that can play at once. Voices past the count set by the `Voices` parameter
+/// stay silent.
+pub const MAX_VOICES: usize = 16;
+
+/// The peak level below which a released voice counts as silent.
+const SILENCE: f32 = 1e-4;
+
+/// How long a released voice has to stay silent before it's freed, in seconds, so that it isn't
+/// cut off while its output crosses zero.
+const SILENCE_TIME: f64 = 0.05;
+
+/// Decides which voice plays a new note when every voice is busy.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoiceStealing {
+    /// Steals the voice whose note started first.
+    Oldest,
+    /// Steals the voice with the lowest output level.
+    Quietest,
+    /// Retriggers the voice that's already playing the same note, even if other voices are free.
+    /// Otherwise steals the oldest voice.
+    #[name = "Same Note"]
+    SameNote,
+}
+
+/// An instance of the control graph that plays one note at a time.
+struct Voice {
+    cg: ControlGraph,
+    /// The (`channel`, `note`) being played, until the voice has been released and its patch
+    /// has fallen silent.
+    note: Option<(u8, u8)>,
+    gate: bool,
+    /// Whether the patch reads [Source::Gate], and so can end its notes with a release of its own.
+    releases: bool,
+    /// Orders voices by when their note started.
+    started: u64,
+    /// The peak level of the last block the voice rendered.
+    level: f32,
+    /// How many samples the voice has been silent for since it was released.
+    silent: usize,
+    /// Follows velocity and aftertouch, gliding between the levels of notes that steal the voice
+    /// so that they don't click. Patches that don't read [Source::Gate] are also faded out by it
+    /// on release, since nothing else ends their notes.
+    gain: Smoother<f32>,
+}
+
+impl Voice {
+    fn new(cg: ControlGraph) -> Self {
+        Self {
+            releases: reads_gate(&cg),
+            cg,
+            note: None,
+            gate: false,
+            started: 0,
+            level: 0.0,
+            silent: 0,
+            gain: Smoother::new(SmoothingStyle::Linear(5.0)),
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.note.is_none()
+    }
+
+    fn note_on(&mut self, sample_rate: f32, channel: u8, note: u8, velocity: f32, started: u64) {
+        // stolen voices carry on from where they were, so that they don't click
+        if self.is_idle() {
+            self.cg.reset_phase();
+
+            // patches that read the gate shape their own attack
+            if self.releases {
+                self.gain.reset(velocity);
+            }
+        }
+
+        self.note = Some((channel, note));
+        self.gate = true;
+        self.started = started;
+
+        self.cg
+            .set_source(Source::NoteNumber, Sample::mono(note as f64));
+        self.cg.set_source(
+            Source::NoteFrequency,
+            Sample::mono(util::midi_note_to_freq(note) as f64),
+        );
+        self.cg
+            .set_source(Source::Velocity, Sample::mono(velocity as f64));
+        self.cg.set_source(Source::Gate, Sample::mono(1.0));
+
+        self.gain.set_target(sample_rate, velocity);
+    }
+
+    fn note_off(&mut self, sample_rate: f32) {
+        self.gate = false;
+        self.silent = 0;
+        self.cg.set_source(Source::Gate, Sample::mono(0.0));
+
+        if !self.releases {
+            self.gain.set_target(sample_rate, 0.0);
+        }
+    }
+
+    /// Stops the voice immediately.
+    fn silence(&mut self) {
+        self.note = None;
+        self.gate = false;
+        self.level = 0.0;
+        self.silent = 0;
+        self.gain.reset(0.0);
+    }
+
+    /// Adds the next `out.len()` samples of the voice to `out`, using `scratch` to render them.
+    fn render(&mut self, scratch: &mut [Sample], out: &mut [Sample]) {
+        if self.is_idle() {
+            return;
+        }
+
+        if self.cg.try_process_block(scratch).is_err() {
+            // output silence while the patch is incomplete
+            scratch.fill(Sample::mono(0.0));
+        }
+
+        self.level = 0.0;
+        for (out, val) in out.iter_mut().zip(scratch.iter()) {
+            let val = *val * self.gain.next() as f64;
+            self.level = self.level.max(val.l().abs().max(val.r().abs()) as f32);
+            *out = *out + val;
+        }
+
+        if self.gate {
+            return;
+        }
+
+        if self.level < SILENCE {
+            self.silent += out.len();
+        } else {
+            self.silent = 0;
+        }
+
+        // released voices are free once their release has fallen silent, or they've faded out
+        let silent_len = (SILENCE_TIME * self.cg.get_sample_rate() as f64) as usize;
+        if self.silent >= silent_len || (!self.releases && !self.gain.is_smoothing()) {
+            self.silence();
+        }
+    }
+}
+
+/// Returns whether any node of `cg` outputs [Source::Gate].
+fn reads_gate(cg: &ControlGraph) -> bool {
+    cg.get_node_indexes()
+        .any(|id| cg.get_node(id).get_source() == Some(Source::Gate))
+}
+
+/// Allocates notes to a fixed set of voices.
+pub(crate) struct Voices {
+    voices: Vec<Voice>,
+    /// Counts every note that's been played, to order voices by age.
+    notes_played: u64,
+    /// Scratch buffer that each voice renders into before it's mixed.
+    scratch: Vec<Sample>,
+}
+
+impl Default for Voices {
+    /// Creates [MAX_VOICES] voices with empty graphs, until [Voices::swap_graphs] is called.
+    fn default() -> Self {
+        Self {
+            voices: (0..MAX_VOICES)
+                .map(|_| Voice::new(ControlGraph::new(0)))
+                .collect(),
+            notes_played: 0,
+            scratch: vec![],
+        }
+    }
+}
+
+impl Voices {
+    /// Allocates room to render at most `max_block_len` samples at once.
+    pub fn set_max_block_len(&mut self, max_block_len: usize) {
+        self.scratch = vec![Sample::default(); max_block_len];
+    }
+
+    /// Swaps the graph of each voice with one of `graphs`, which must hold [MAX_VOICES] graphs,
+    /// carrying over the state of nodes that are in both.
+    pub fn swap_graphs(&mut self, graphs: &mut [ControlGraph]) {
+        for (voice, cg) in self.voices.iter_mut().zip(graphs) {
+            cg.carry_state_from(&voice.cg);
+            std::mem::swap(&mut voice.cg, cg);
+            voice.releases = reads_gate(&voice.cg);
+        }
+    }
+
+    /// Plays `note` on one of the first `count` voices, stealing one if they're all busy.
+    pub fn note_on(
+        &mut self,
+        count: usize,
+        stealing: VoiceStealing,
+        sample_rate: f32,
+        (channel, note): (u8, u8),
+        velocity: f32,
+    ) {
+        let Some(i) = self.pick(count, stealing, (channel, note)) else {
+            return;
+        };
+
+        self.notes_played += 1;
+        self.voices[i].note_on(sample_rate, channel, note, velocity, self.notes_played);
+    }
+
+    /// Releases every voice that's holding `note`.
+    pub fn note_off(&mut self, sample_rate: f32, note: (u8, u8)) {
+        for voice in &mut self.voices {
+            if voice.gate && voice.note == Some(note) {
+                voice.note_off(sample_rate);
+            }
+        }
+    }
+
+    /// Sets the gain and aftertouch of every voice that's holding `note` to `pressure`.
+    pub fn pressure(&mut self, sample_rate: f32, note: (u8, u8), pressure: f32) {
+        for voice in &mut self.voices {
+            if voice.gate && voice.note == Some(note) {
+                voice.gain.set_target(sample_rate, pressure);
+                voice
+                    .cg
+                    .set_source(Source::Aftertouch, Sample::mono(pressure as f64));
+            }
+        }
+    }
+
+    /// Sets `source` in every voice, for events that apply to the whole channel.
+    pub fn set_source(&mut self, source: Source, value: Sample) {
+        for voice in &mut self.voices {
+            voice.cg.set_source(source, value);
+        }
+    }
+
+    /// Adds the next `out.len()` samples of the first `count` voices to `out`, and silences the
+    /// rest.
+    pub fn render(&mut self, count: usize, out: &mut [Sample]) {
+        let count = count.min(self.voices.len());
+        let scratch = &mut self.scratch[..out.len()];
+
+        for voice in &mut self.voices[..count] {
+            voice.render(scratch, out);
+        }
+
+        for voice in &mut self.voices[count..] {
+            voice.silence();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.voices.iter_mut().for_each(Voice::silence);
+    }
+
+    /// Returns the voice that should play `note`, if there are any voices.
+    fn pick(&self, count: usize, stealing: VoiceStealing, note: (u8, u8)) -> Option<usize> {
+        let voices = &self.voices[..count.min(self.voices.len())];
+
+        let same_note = voices.iter().position(|v| v.note == Some(note));
+        if let (VoiceStealing::SameNote, Some(i)) = (stealing, same_note) {
+            return Some(i);
+        }
+
+        if let Some(i) = voices.iter().position(Voice::is_idle) {
+            return Some(i);
+        }
+
+        let stolen = match stealing {
+            VoiceStealing::Oldest | VoiceStealing::SameNote => {
+                voices.iter().enumerate().min_by_key(|(_, v)| v.started)
+            }
+            VoiceStealing::Quietest => voices
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.level.total_cmp(&b.level)),
+        };
+
+        stolen.map(|(i, _)| i)
+    }
+}